@@ -68,29 +68,9 @@ const HMAC_KEY_HEX: &str = match option_env!("HORSEACT_HMAC_KEY") {
 };
 const HMAC_KEY: [u8; 32] = decode_key(HMAC_KEY_HEX);
 
-pub fn dispatch(endpoint: &str, data: Value) {
-    let sv = server_url();
-    if sv.is_empty() {
-        log!("[API] Dispatch skipped for {}: no server URL configured", endpoint);
-        return;
-    }
-
-    let fields: Vec<String> = endpoint_configs().iter()
-        .find(|e| e.name == endpoint)
-        .map(|e| e.fields.clone())
-        .unwrap_or_default();
-
-    let server_url_owned = sv.to_string();
-    let api_key_owned = api_key().to_string();
-    let endpoint_owned = endpoint.to_string();
-    let payload = extract_fields(&data, &fields);
-
-    std::thread::spawn(move || {
-        send(&server_url_owned, &api_key_owned, &endpoint_owned, &payload);
-    });
-}
-
-fn extract_fields(data: &Value, fields: &[String]) -> Value {
+/// Keeps only the dot-separated `fields` of `data`, or all of it when no
+/// fields are given.
+pub fn extract_fields(data: &Value, fields: &[String]) -> Value {
     if fields.is_empty() {
         return data.clone();
     }
@@ -149,25 +129,25 @@ fn sign(data: &[u8]) -> String {
     hex::encode(mac.finalize().into_bytes())
 }
 
-fn send(server_url: &str, api_key: &str, endpoint: &str, data: &Value) {
+pub fn send(server_url: &str, api_key: &str, endpoint: &str, data: &Value) -> bool {
     let json_bytes = match serde_json::to_vec(data) {
         Ok(b) => b,
         Err(e) => {
             log!("[API] Failed to serialize {}: {}", endpoint, e);
-            return;
+            return false;
         }
     };
 
     let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
     if let Err(e) = encoder.write_all(&json_bytes) {
         log!("[API] Failed to compress {}: {}", endpoint, e);
-        return;
+        return false;
     }
     let compressed = match encoder.finish() {
         Ok(c) => c,
         Err(e) => {
             log!("[API] Failed to finalize compression for {}: {}", endpoint, e);
-            return;
+            return false;
         }
     };
 
@@ -183,9 +163,11 @@ fn send(server_url: &str, api_key: &str, endpoint: &str, data: &Value) {
     {
         Ok(resp) => {
             log!("[API] {} -> server: {}", endpoint, resp.status());
+            true
         }
         Err(e) => {
             log!("[API] Failed to send {}: {}", endpoint, e);
+            false
         }
     }
 }
@@ -0,0 +1,198 @@
+use crate::config::{builds_favourites_only, builds_min_rank_score, export_builds, save_root};
+use crate::log;
+use crate::master::{self, NameKind};
+use crate::models::{Aptitudes, RunningStyle, Stats, TrainedChara};
+use crate::persistence::write_atomic;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+
+/// Folder of the save root the builds are written to. It is never scanned
+/// for captures.
+pub const BUILDS_DIR: &str = "Builds";
+
+/// Bumped whenever a member of [`Build`] is renamed or removed.
+const BUILD_VERSION: u32 = 1;
+
+/// Everything a race simulator needs to know about one veteran.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Build {
+    build_version: u32,
+    trained_chara_id: i64,
+    card_id: Option<i64>,
+    chara_id: Option<i64>,
+    /// Card name from the master database.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    rank_score: Option<i64>,
+    favourite: bool,
+    /// Strategy the veteran was trained with, e.g. `Pace Chaser`.
+    strategy: Option<&'static str>,
+    stats: Stats,
+    aptitudes: BuildAptitudes,
+    skills: Vec<BuildSkill>,
+    unique_skill_level: Option<i64>,
+}
+
+/// Aptitudes as grade letters, `G` to `S`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BuildAptitudes {
+    turf: Option<&'static str>,
+    dirt: Option<&'static str>,
+    short: Option<&'static str>,
+    mile: Option<&'static str>,
+    medium: Option<&'static str>,
+    long: Option<&'static str>,
+    front_runner: Option<&'static str>,
+    pace_chaser: Option<&'static str>,
+    late_surger: Option<&'static str>,
+    end_closer: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BuildSkill {
+    skill_id: i64,
+    level: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+fn grade(value: Option<i64>) -> Option<&'static str> {
+    match value? {
+        1 => Some("G"),
+        2 => Some("F"),
+        3 => Some("E"),
+        4 => Some("D"),
+        5 => Some("C"),
+        6 => Some("B"),
+        7 => Some("A"),
+        8 => Some("S"),
+        _ => None,
+    }
+}
+
+// Unique skills are numbered 1xxxxx; the inherited versions other veterans
+// get from their parents are 9xxxxx.
+fn is_unique_skill(skill_id: i64) -> bool {
+    skill_id / 100_000 == 1
+}
+
+impl BuildAptitudes {
+    fn from_aptitudes(a: &Aptitudes) -> Self {
+        BuildAptitudes {
+            turf: grade(a.turf),
+            dirt: grade(a.dirt),
+            short: grade(a.short),
+            mile: grade(a.mile),
+            medium: grade(a.medium),
+            long: grade(a.long),
+            front_runner: grade(a.front_runner),
+            pace_chaser: grade(a.pace_chaser),
+            late_surger: grade(a.late_surger),
+            end_closer: grade(a.end_closer),
+        }
+    }
+}
+
+impl Build {
+    fn from_chara(chara: TrainedChara) -> Self {
+        Build {
+            build_version: BUILD_VERSION,
+            trained_chara_id: chara.trained_chara_id,
+            card_id: chara.card_id,
+            chara_id: chara.chara_id,
+            name: chara
+                .card_id
+                .and_then(|id| master::name(NameKind::Card, id)),
+            rank_score: chara.rank_score,
+            favourite: chara.is_favourite.unwrap_or(false),
+            strategy: chara.running_style.map(RunningStyle::name),
+            aptitudes: BuildAptitudes::from_aptitudes(&chara.aptitudes),
+            unique_skill_level: chara
+                .skills
+                .iter()
+                .find(|s| is_unique_skill(s.skill_id))
+                .and_then(|s| s.level),
+            skills: chara
+                .skills
+                .iter()
+                .map(|s| BuildSkill {
+                    skill_id: s.skill_id,
+                    level: s.level,
+                    name: master::name(NameKind::Skill, s.skill_id),
+                })
+                .collect(),
+            stats: chara.stats,
+        }
+    }
+}
+
+fn builds_dir() -> PathBuf {
+    save_root().join(BUILDS_DIR)
+}
+
+fn selected(chara: &TrainedChara) -> bool {
+    (!builds_favourites_only() || chara.is_favourite == Some(true))
+        && chara.rank_score.unwrap_or(0) >= builds_min_rank_score()
+}
+
+/// Writes `Builds/<trainedCharaId>.json` for every veteran passing the
+/// `buildsFavouritesOnly` and `buildsMinRankScore` filters, and removes the
+/// builds of veterans that no longer do or have left the list, so the folder
+/// mirrors it.
+pub fn export(list: &Value) {
+    if !export_builds() {
+        return;
+    }
+    if let Err(e) = write(list) {
+        log!("[Builds] Failed to export builds: {}", e);
+    }
+}
+
+fn write(list: &Value) -> Result<(), String> {
+    let dir = builds_dir();
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+
+    let mut written = HashSet::new();
+    let mut filtered_out = HashSet::new();
+    // Builds of veterans missing from the list are only removed when every
+    // entry could be read; an unreadable one may be a veteran still owned.
+    let mut complete = list.is_array();
+    for chara in list.as_array().into_iter().flatten() {
+        let chara = match TrainedChara::from_value(chara) {
+            Ok(chara) => chara,
+            Err(e) => {
+                log!("[Builds] Skipped a veteran: {}", e);
+                complete = false;
+                continue;
+            }
+        };
+        if !selected(&chara) {
+            filtered_out.insert(format!("{}.json", chara.trained_chara_id));
+            continue;
+        }
+        let name = format!("{}.json", chara.trained_chara_id);
+        let bytes =
+            serde_json::to_vec_pretty(&Build::from_chara(chara)).map_err(|e| e.to_string())?;
+        // The veteran list arrives often and mostly unchanged.
+        let path = dir.join(&name);
+        if fs::read(&path).ok().as_deref() != Some(bytes.as_slice()) {
+            write_atomic(&path, &bytes).map_err(|e| e.to_string())?;
+        }
+        written.insert(name);
+    }
+
+    for entry in fs::read_dir(&dir).map_err(|e| e.to_string())?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let stale = filtered_out.contains(&name) || (complete && !written.contains(&name));
+        if name.ends_with(".json") && stale {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+    Ok(())
+}
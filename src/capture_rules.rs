@@ -0,0 +1,71 @@
+use crate::config::{capture_rules, CaptureRule, RuleAction};
+use crate::course::CourseInfo;
+use crate::models::RaceInfo;
+use serde_json::Value;
+
+/// Outcome of the `captureRules` for one race.
+pub struct Verdict {
+    pub save: bool,
+    /// Tags of every matching `save` or `tag` rule, without duplicates.
+    pub tags: Vec<String>,
+}
+
+/// A race is saved unless a `skip` rule matches, and, when there are `save`
+/// rules, only if one of them matches. Without rules every race is saved.
+pub fn evaluate(race_type: Option<&str>, course: &CourseInfo, race: Option<&RaceInfo>) -> Verdict {
+    let rules = capture_rules();
+    let mut tags: Vec<String> = Vec::new();
+    let mut skipped = false;
+    let mut saved = !rules.iter().any(|r| r.action == RuleAction::Save);
+
+    for rule in rules.iter().filter(|r| matches(r, race_type, course, race)) {
+        match rule.action {
+            RuleAction::Skip => skipped = true,
+            RuleAction::Save => saved = true,
+            RuleAction::Tag => {}
+        }
+        if rule.action != RuleAction::Skip {
+            for tag in &rule.tags {
+                if !tags.contains(tag) {
+                    tags.push(tag.clone());
+                }
+            }
+        }
+    }
+
+    Verdict {
+        save: saved && !skipped,
+        tags,
+    }
+}
+
+fn matches(
+    rule: &CaptureRule,
+    race_type: Option<&str>,
+    course: &CourseInfo,
+    race: Option<&RaceInfo>,
+) -> bool {
+    let id_in = |ids: &[i64], value: &Option<Value>| {
+        ids.is_empty()
+            || value
+                .as_ref()
+                .and_then(|v| v.as_i64())
+                .is_some_and(|id| ids.contains(&id))
+    };
+    let horses = race.map(|r| r.horses.as_slice()).unwrap_or(&[]);
+
+    (rule.race_types.is_empty()
+        || race_type.is_some_and(|t| rule.race_types.iter().any(|r| r == t)))
+        && id_in(&rule.course_ids, &course.course_id)
+        && id_in(&rule.track_ids, &course.track_id)
+        && id_in(&rule.race_instance_ids, &course.race_instance_id)
+        && (rule.chara_ids.is_empty()
+            || horses
+                .iter()
+                .any(|h| h.chara_id.is_some_and(|id| rule.chara_ids.contains(&id))))
+        && rule.my_horse_in_top.is_none_or(|top| {
+            horses
+                .iter()
+                .any(|h| h.is_mine == Some(true) && h.finish_order < top)
+        })
+}
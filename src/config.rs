@@ -1,5 +1,7 @@
+use crate::envelope::CaptureType;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     env,
     fs::{create_dir_all, read_to_string, File, OpenOptions},
     io::Write,
@@ -14,6 +16,32 @@ static SERVER_URL: OnceLock<String> = OnceLock::new();
 static FIELD_BLACKLIST: OnceLock<Vec<String>> = OnceLock::new();
 static SAVE_CAREER_RACES: OnceLock<bool> = OnceLock::new();
 static SAVE_TT_RACES: OnceLock<bool> = OnceLock::new();
+static RACE_FILE_TEMPLATE: OnceLock<String> = OnceLock::new();
+static TEAM_TRIALS_FILE_TEMPLATE: OnceLock<String> = OnceLock::new();
+static API_RESPONSE_FILE_TEMPLATE: OnceLock<String> = OnceLock::new();
+static VETERANS_FILE_TEMPLATE: OnceLock<String> = OnceLock::new();
+static FORCE_SAVE_DUPLICATES: OnceLock<bool> = OnceLock::new();
+static SINKS: OnceLock<Vec<SinkConfig>> = OnceLock::new();
+static OUTPUT_FORMATS: OnceLock<OutputFormats> = OnceLock::new();
+static CSV_EXPORT: OnceLock<CsvExport> = OnceLock::new();
+static CSV_COLUMNS: OnceLock<Vec<String>> = OnceLock::new();
+static CONVERT_TO_PARQUET: OnceLock<bool> = OnceLock::new();
+static RETENTION: OnceLock<Vec<RetentionRule>> = OnceLock::new();
+static RETENTION_INTERVAL_MINUTES: OnceLock<u64> = OnceLock::new();
+static SIM_DATA_SIDECAR: OnceLock<SimDataSidecar> = OnceLock::new();
+static HTML_REPORT: OnceLock<bool> = OnceLock::new();
+static GENERATE_HTML_REPORTS: OnceLock<bool> = OnceLock::new();
+static ENRICH_NAMES: OnceLock<bool> = OnceLock::new();
+static MASTER_DB_PATH: OnceLock<PathBuf> = OnceLock::new();
+static JSON_SCHEMAS: OnceLock<bool> = OnceLock::new();
+static EXPORT_BUILDS: OnceLock<bool> = OnceLock::new();
+static SPLIT_TEAM_TRIALS: OnceLock<bool> = OnceLock::new();
+static KEEP_TEAM_TRIALS_RESPONSE: OnceLock<bool> = OnceLock::new();
+static BUILDS_FAVOURITES_ONLY: OnceLock<bool> = OnceLock::new();
+static BUILDS_MIN_RANK_SCORE: OnceLock<i64> = OnceLock::new();
+static SAVE_RACE_TYPES: OnceLock<BTreeMap<String, bool>> = OnceLock::new();
+static RACE_TYPE_FOLDERS: OnceLock<BTreeMap<String, String>> = OnceLock::new();
+static CAPTURE_RULES: OnceLock<Vec<CaptureRule>> = OnceLock::new();
 static LOG_MUTEX: Mutex<()> = Mutex::new(());
 
 fn default_field_blacklist() -> Vec<String> {
@@ -29,6 +57,145 @@ fn default_field_blacklist() -> Vec<String> {
     ]
 }
 
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    Json,
+    Sqlite,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkKind {
+    Directory,
+    Sqlite,
+    Http,
+    Socket,
+    Parquet,
+}
+
+/// One output destination. Empty `captureTypes` / `raceTypes` accept
+/// everything; `path`, `url`, `apiKey` and `address` only apply to the sink
+/// types that use them.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct SinkConfig {
+    #[serde(rename = "type")]
+    pub kind: SinkKind,
+    #[serde(
+        rename = "captureTypes",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub capture_types: Vec<String>,
+    #[serde(rename = "raceTypes", default, skip_serializing_if = "Vec::is_empty")]
+    pub race_types: Vec<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub path: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub url: String,
+    #[serde(rename = "apiKey", default, skip_serializing_if = "String::is_empty")]
+    pub api_key: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub address: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Pretty,
+    Compact,
+    Gzip,
+    Ndjson,
+    Msgpack,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default)]
+pub struct OutputFormats {
+    #[serde(default)]
+    race: OutputFormat,
+    #[serde(rename = "teamTrials", default)]
+    team_trials: OutputFormat,
+    #[serde(default)]
+    veterans: OutputFormat,
+    #[serde(rename = "apiResponse", default)]
+    api_response: OutputFormat,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum CsvExport {
+    #[default]
+    Off,
+    PerRace,
+    Rolling,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SimDataSidecar {
+    #[default]
+    Off,
+    Json,
+    Csv,
+    Both,
+}
+
+/// Limits for the capture files under one folder of the save root. A limit
+/// of 0 means no limit.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct RetentionRule {
+    pub folder: String,
+    #[serde(rename = "maxAgeDays", default)]
+    pub max_age_days: u64,
+    #[serde(rename = "maxCount", default)]
+    pub max_count: usize,
+    #[serde(rename = "maxSizeMb", default)]
+    pub max_size_mb: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    #[default]
+    Save,
+    Skip,
+    Tag,
+}
+
+/// A condition on races evaluated before saving. Every condition that is set
+/// must hold for the rule to match.
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct CaptureRule {
+    #[serde(default)]
+    pub action: RuleAction,
+    #[serde(rename = "raceTypes", default, skip_serializing_if = "Vec::is_empty")]
+    pub race_types: Vec<String>,
+    #[serde(rename = "courseIds", default, skip_serializing_if = "Vec::is_empty")]
+    pub course_ids: Vec<i64>,
+    #[serde(rename = "trackIds", default, skip_serializing_if = "Vec::is_empty")]
+    pub track_ids: Vec<i64>,
+    #[serde(
+        rename = "raceInstanceIds",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub race_instance_ids: Vec<i64>,
+    /// Matches when any horse is one of these charas.
+    #[serde(rename = "charaIds", default, skip_serializing_if = "Vec::is_empty")]
+    pub chara_ids: Vec<i64>,
+    /// Matches when one of the player's horses placed this high or better.
+    #[serde(
+        rename = "myHorseInTop",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub my_horse_in_top: Option<i64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
 #[derive(Deserialize, Serialize)]
 struct Config {
     #[serde(rename = "outputPath")]
@@ -43,6 +210,74 @@ struct Config {
     save_career_races: bool,
     #[serde(rename = "saveTTRaces", default = "default_save_tt_races")]
     save_tt_races: bool,
+    #[serde(rename = "splitTeamTrials", default = "default_split_team_trials")]
+    split_team_trials: bool,
+    #[serde(rename = "keepTeamTrialsResponse", default)]
+    keep_team_trials_response: bool,
+    #[serde(rename = "saveRaceTypes", default = "default_save_race_types")]
+    save_race_types: BTreeMap<String, bool>,
+    #[serde(rename = "captureRules", default)]
+    capture_rules: Vec<CaptureRule>,
+    #[serde(rename = "raceTypeFolders", default = "default_race_type_folders")]
+    race_type_folders: BTreeMap<String, String>,
+    #[serde(rename = "raceFileTemplate", default = "default_race_file_template")]
+    race_file_template: String,
+    #[serde(
+        rename = "teamTrialsFileTemplate",
+        default = "default_team_trials_file_template"
+    )]
+    team_trials_file_template: String,
+    #[serde(
+        rename = "apiResponseFileTemplate",
+        default = "default_api_response_file_template"
+    )]
+    api_response_file_template: String,
+    #[serde(
+        rename = "veteransFileTemplate",
+        default = "default_veterans_file_template"
+    )]
+    veterans_file_template: String,
+    #[serde(rename = "forceSaveDuplicates", default)]
+    force_save_duplicates: bool,
+    #[serde(rename = "storageBackend", default)]
+    storage_backend: StorageBackend,
+    #[serde(rename = "sqlitePath", default)]
+    sqlite_path: String,
+    #[serde(default)]
+    sinks: Vec<SinkConfig>,
+    #[serde(rename = "outputFormats", default)]
+    output_formats: OutputFormats,
+    #[serde(rename = "csvExport", default)]
+    csv_export: CsvExport,
+    #[serde(rename = "csvColumns", default = "default_csv_columns")]
+    csv_columns: Vec<String>,
+    #[serde(rename = "convertToParquet", default)]
+    convert_to_parquet: bool,
+    #[serde(default)]
+    retention: Vec<RetentionRule>,
+    #[serde(
+        rename = "retentionIntervalMinutes",
+        default = "default_retention_interval_minutes"
+    )]
+    retention_interval_minutes: u64,
+    #[serde(rename = "simDataSidecar", default)]
+    sim_data_sidecar: SimDataSidecar,
+    #[serde(rename = "htmlReport", default)]
+    html_report: bool,
+    #[serde(rename = "generateHtmlReports", default)]
+    generate_html_reports: bool,
+    #[serde(rename = "enrichNames", default = "default_enrich_names")]
+    enrich_names: bool,
+    #[serde(rename = "masterDbPath", default = "default_master_db_path")]
+    master_db_path: String,
+    #[serde(rename = "jsonSchemas", default = "default_json_schemas")]
+    json_schemas: bool,
+    #[serde(rename = "exportBuilds", default)]
+    export_builds: bool,
+    #[serde(rename = "buildsFavouritesOnly", default)]
+    builds_favourites_only: bool,
+    #[serde(rename = "buildsMinRankScore", default)]
+    builds_min_rank_score: i64,
 }
 
 impl Default for Config {
@@ -54,10 +289,50 @@ impl Default for Config {
             field_blacklist: default_field_blacklist(),
             save_career_races: default_save_career_races(),
             save_tt_races: default_save_tt_races(),
+            split_team_trials: default_split_team_trials(),
+            keep_team_trials_response: false,
+            save_race_types: default_save_race_types(),
+            capture_rules: Vec::new(),
+            race_type_folders: default_race_type_folders(),
+            race_file_template: default_race_file_template(),
+            team_trials_file_template: default_team_trials_file_template(),
+            api_response_file_template: default_api_response_file_template(),
+            veterans_file_template: default_veterans_file_template(),
+            force_save_duplicates: false,
+            storage_backend: StorageBackend::default(),
+            sqlite_path: String::new(),
+            sinks: Vec::new(),
+            output_formats: OutputFormats::default(),
+            csv_export: CsvExport::default(),
+            csv_columns: default_csv_columns(),
+            convert_to_parquet: false,
+            retention: Vec::new(),
+            retention_interval_minutes: default_retention_interval_minutes(),
+            sim_data_sidecar: SimDataSidecar::default(),
+            html_report: false,
+            generate_html_reports: false,
+            enrich_names: default_enrich_names(),
+            master_db_path: default_master_db_path(),
+            json_schemas: default_json_schemas(),
+            export_builds: false,
+            builds_favourites_only: false,
+            builds_min_rank_score: 0,
         }
     }
 }
 
+fn default_enrich_names() -> bool {
+    true
+}
+
+fn default_master_db_path() -> String {
+    "%USERPROFILE%\\AppData\\LocalLow\\Cygames\\umamusume\\master\\master.mdb".to_string()
+}
+
+fn default_json_schemas() -> bool {
+    true
+}
+
 fn default_save_career_races() -> bool {
     true
 }
@@ -66,6 +341,79 @@ fn default_save_tt_races() -> bool {
     false
 }
 
+fn default_split_team_trials() -> bool {
+    true
+}
+
+fn default_save_race_types() -> BTreeMap<String, bool> {
+    ["RoomMatch", "Champions", "Practice", "Single", "Other"]
+        .into_iter()
+        .map(|race_type| (race_type.to_string(), true))
+        .collect()
+}
+
+fn default_race_type_folders() -> BTreeMap<String, String> {
+    [
+        ("Single", "Career"),
+        ("RoomMatch", "Room match"),
+        ("Champions", "Champions meeting"),
+        ("Practice", "Practice room"),
+        ("TeamStadium", "Team trials"),
+        ("Legend", "Legend races"),
+        ("Daily", "Daily races"),
+        ("Event", "Event races"),
+        ("Story", "Story races"),
+        ("ChallengeMatch", "Challenge match"),
+        ("Heroes", "Heroes"),
+    ]
+    .into_iter()
+    .map(|(race_type, folder)| (race_type.to_string(), folder.to_string()))
+    .collect()
+}
+
+fn default_race_file_template() -> String {
+    "{raceType}/{winner}-{finishTime}s-{date:%Y%m%d}".to_string()
+}
+
+fn default_team_trials_file_template() -> String {
+    "Team trials/TT-{timestamp}".to_string()
+}
+
+fn default_api_response_file_template() -> String {
+    "API responses/{endpoint}-{timestamp}".to_string()
+}
+
+fn default_veterans_file_template() -> String {
+    "veterans".to_string()
+}
+
+fn default_csv_columns() -> Vec<String> {
+    [
+        "capture.capturedAt.local",
+        "race.raceType",
+        "race.trackId",
+        "race.courseId",
+        "race.distance",
+        "race.surface",
+        "race.groundCondition",
+        "horse.FinishOrder",
+        "horse.FinishTimeRaw",
+        "horse.FinishTime",
+        "horse.FrameOrder",
+        "horse.charaName",
+        "horse.GateNo",
+        "horse.RunningStyle",
+        "horse.Popularity",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect()
+}
+
+fn default_retention_interval_minutes() -> u64 {
+    60
+}
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct EndpointConfig {
     pub name: String,
@@ -87,6 +435,14 @@ pub fn save_root() -> &'static PathBuf {
     SAVE_ROOT.get().expect("save root not initialized")
 }
 
+/// Path of a saved file relative to the save root, always with `/` separators.
+pub fn relative_to_save_root(path: &Path) -> String {
+    path.strip_prefix(save_root())
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
 pub fn field_blacklist() -> &'static Vec<String> {
     FIELD_BLACKLIST.get().expect("field blacklist not initialized")
 }
@@ -103,6 +459,165 @@ pub fn save_tt_races() -> bool {
         .expect("save TT races flag not initialized")
 }
 
+pub fn split_team_trials() -> bool {
+    *SPLIT_TEAM_TRIALS
+        .get()
+        .expect("split team trials flag not initialized")
+}
+
+pub fn keep_team_trials_response() -> bool {
+    *KEEP_TEAM_TRIALS_RESPONSE
+        .get()
+        .expect("keep team trials response flag not initialized")
+}
+
+/// Whether races of the given raw `<RaceType>` are saved. Types missing from
+/// `saveRaceTypes` follow its `Other` entry.
+pub fn save_race_type(race_type: Option<&str>) -> bool {
+    let toggles = SAVE_RACE_TYPES
+        .get()
+        .expect("save race types not initialized");
+    race_type
+        .and_then(|t| toggles.get(t))
+        .or_else(|| toggles.get("Other"))
+        .copied()
+        .unwrap_or(true)
+}
+
+/// Folder name of a raw `<RaceType>`, or `None` when it is not mapped.
+pub fn race_type_folder(race_type: &str) -> Option<&'static str> {
+    RACE_TYPE_FOLDERS
+        .get()
+        .expect("race type folders not initialized")
+        .get(race_type)
+        .map(|folder| folder.as_str())
+}
+
+pub fn capture_rules() -> &'static [CaptureRule] {
+    CAPTURE_RULES.get().expect("capture rules not initialized")
+}
+
+pub fn race_file_template() -> &'static str {
+    RACE_FILE_TEMPLATE
+        .get()
+        .expect("race file template not initialized")
+}
+
+pub fn team_trials_file_template() -> &'static str {
+    TEAM_TRIALS_FILE_TEMPLATE
+        .get()
+        .expect("team trials file template not initialized")
+}
+
+pub fn api_response_file_template() -> &'static str {
+    API_RESPONSE_FILE_TEMPLATE
+        .get()
+        .expect("API response file template not initialized")
+}
+
+pub fn veterans_file_template() -> &'static str {
+    VETERANS_FILE_TEMPLATE
+        .get()
+        .expect("veterans file template not initialized")
+}
+
+pub fn force_save_duplicates() -> bool {
+    *FORCE_SAVE_DUPLICATES
+        .get()
+        .expect("force save duplicates flag not initialized")
+}
+
+/// The sinks captures are routed to, with paths resolved and defaults filled in.
+pub fn sinks() -> &'static [SinkConfig] {
+    SINKS.get().expect("sinks not initialized")
+}
+
+pub fn output_format(capture_type: CaptureType) -> OutputFormat {
+    let formats = OUTPUT_FORMATS
+        .get()
+        .expect("output formats not initialized");
+    match capture_type {
+        CaptureType::Race => formats.race,
+        CaptureType::TeamTrials => formats.team_trials,
+        CaptureType::Veterans => formats.veterans,
+        CaptureType::ApiResponse => formats.api_response,
+    }
+}
+
+pub fn csv_export() -> CsvExport {
+    *CSV_EXPORT.get().expect("CSV export mode not initialized")
+}
+
+pub fn csv_columns() -> &'static [String] {
+    CSV_COLUMNS.get().expect("CSV columns not initialized")
+}
+
+/// Whether the saved archive should be converted to Parquet once at startup.
+pub fn convert_to_parquet() -> bool {
+    *CONVERT_TO_PARQUET
+        .get()
+        .expect("convert to parquet flag not initialized")
+}
+
+pub fn retention_rules() -> &'static [RetentionRule] {
+    RETENTION.get().expect("retention rules not initialized")
+}
+
+pub fn retention_interval_minutes() -> u64 {
+    *RETENTION_INTERVAL_MINUTES
+        .get()
+        .expect("retention interval not initialized")
+}
+
+pub fn sim_data_sidecar() -> SimDataSidecar {
+    *SIM_DATA_SIDECAR
+        .get()
+        .expect("simulation sidecar mode not initialized")
+}
+
+pub fn html_report() -> bool {
+    *HTML_REPORT.get().expect("HTML report flag not initialized")
+}
+
+/// Whether HTML reports should be generated for the whole archive once at
+/// startup.
+pub fn generate_html_reports() -> bool {
+    *GENERATE_HTML_REPORTS
+        .get()
+        .expect("generate HTML reports flag not initialized")
+}
+
+pub fn enrich_names() -> bool {
+    *ENRICH_NAMES.get().expect("enrich names flag not initialized")
+}
+
+/// Location of the game's master database, used to resolve ids to names.
+pub fn master_db_path() -> &'static PathBuf {
+    MASTER_DB_PATH
+        .get()
+        .expect("master database path not initialized")
+}
+
+pub fn json_schemas() -> bool {
+    *JSON_SCHEMAS.get().expect("JSON schemas flag not initialized")
+}
+
+pub fn export_builds() -> bool {
+    *EXPORT_BUILDS.get().expect("export builds flag not initialized")
+}
+
+pub fn builds_favourites_only() -> bool {
+    *BUILDS_FAVOURITES_ONLY
+        .get()
+        .expect("builds favourites flag not initialized")
+}
+
+pub fn builds_min_rank_score() -> i64 {
+    *BUILDS_MIN_RANK_SCORE
+        .get()
+        .expect("builds minimum rank score not initialized")
+}
+
 pub fn is_field_blacklisted(name: &str, sensitive_fields: &[String]) -> bool {
     if sensitive_fields.iter().any(|pattern| name == pattern) {
         return false;
@@ -123,7 +638,7 @@ pub fn init_paths() -> Result<(), String> {
 
     let cfg_path = cfg_dir.join("horseACTConfig.json");
 
-    let cfg: Config = if cfg_path.exists() {
+    let mut cfg: Config = if cfg_path.exists() {
         read_to_string(&cfg_path)
             .ok()
             .and_then(|s| serde_json::from_str(&s).ok())
@@ -132,6 +647,11 @@ pub fn init_paths() -> Result<(), String> {
         Config::default()
     };
 
+    // One-shot actions run for this start only and are switched off again
+    // when the config is written back.
+    let convert_to_parquet = std::mem::take(&mut cfg.convert_to_parquet);
+    let generate_html_reports = std::mem::take(&mut cfg.generate_html_reports);
+
     // Always re-write config to add new fields and remove obsolete ones
     if let Ok(mut f) = File::create(&cfg_path) {
         let _ = writeln!(
@@ -142,20 +662,7 @@ pub fn init_paths() -> Result<(), String> {
     }
 
     let resolved_root = match cfg.output_path.as_deref() {
-        Some(p) if !p.trim().is_empty() => {
-            let path_str = p.trim();
-            let expanded_path = if let Ok(home) = env::var("USERPROFILE") {
-                path_str.replace("%USERPROFILE%", &home)
-            } else {
-                path_str.to_string()
-            };
-            let path = Path::new(&expanded_path);
-            if path.is_absolute() {
-                path.to_path_buf()
-            } else {
-                plugin_dir.join(path)
-            }
-        }
+        Some(p) if !p.trim().is_empty() => resolve_path(p, &plugin_dir),
         _ => plugin_dir.clone(),
     };
 
@@ -178,15 +685,147 @@ pub fn init_paths() -> Result<(), String> {
         }
     }
 
+    let sqlite_path = if cfg.sqlite_path.trim().is_empty() {
+        saved.join("horseACT.sqlite3")
+    } else {
+        resolve_path(&cfg.sqlite_path, &plugin_dir)
+    };
+
+    let master_db_path = resolve_path(&cfg.master_db_path, &plugin_dir);
+
+    // Without explicit sinks, storageBackend decides where captures go.
+    // Endpoint responses are always uploaded to serverUrl, unless a sink
+    // already does so.
+    let mut sinks = cfg.sinks.clone();
+    let sink = |kind, capture_types: &[CaptureType]| SinkConfig {
+        kind,
+        capture_types: capture_types
+            .iter()
+            .map(|t| t.as_str().to_string())
+            .collect(),
+        race_types: Vec::new(),
+        path: String::new(),
+        url: String::new(),
+        api_key: String::new(),
+        address: String::new(),
+    };
+    if sinks.is_empty() {
+        let kind = match cfg.storage_backend {
+            StorageBackend::Json => SinkKind::Directory,
+            StorageBackend::Sqlite => SinkKind::Sqlite,
+        };
+        sinks.push(sink(
+            kind,
+            &[
+                CaptureType::Race,
+                CaptureType::TeamTrials,
+                CaptureType::Veterans,
+            ],
+        ));
+    }
+    let uploads_responses = sinks.iter().any(|s| {
+        matches!(s.kind, SinkKind::Http)
+            && (s.url.is_empty() || s.url == cfg.server_url)
+            && s.capture_types.iter().any(|t| t == CaptureType::ApiResponse.as_str())
+    });
+    if !cfg.server_url.is_empty() && !uploads_responses {
+        sinks.push(sink(SinkKind::Http, &[CaptureType::ApiResponse]));
+    }
+    for sink in &mut sinks {
+        match sink.kind {
+            SinkKind::Directory => {
+                sink.path = if sink.path.trim().is_empty() {
+                    saved.to_string_lossy().to_string()
+                } else {
+                    resolve_path(&sink.path, &plugin_dir)
+                        .to_string_lossy()
+                        .to_string()
+                };
+            }
+            SinkKind::Sqlite => {
+                sink.path = if sink.path.trim().is_empty() {
+                    sqlite_path.to_string_lossy().to_string()
+                } else {
+                    resolve_path(&sink.path, &plugin_dir)
+                        .to_string_lossy()
+                        .to_string()
+                };
+            }
+            SinkKind::Http => {
+                if sink.url.is_empty() {
+                    sink.url = cfg.server_url.clone();
+                }
+                if sink.api_key.is_empty() {
+                    sink.api_key = cfg.api_key.clone();
+                }
+            }
+            SinkKind::Parquet => {
+                sink.path = if sink.path.trim().is_empty() {
+                    saved.join("Parquet").to_string_lossy().to_string()
+                } else {
+                    resolve_path(&sink.path, &plugin_dir)
+                        .to_string_lossy()
+                        .to_string()
+                };
+            }
+            SinkKind::Socket => {}
+        }
+    }
+
     SAVE_ROOT.set(saved).map_err(|_| "SAVE_ROOT was already initialized".to_string())?;
     let _ = API_KEY.set(cfg.api_key);
     let _ = SERVER_URL.set(cfg.server_url);
     let _ = FIELD_BLACKLIST.set(cfg.field_blacklist);
     let _ = SAVE_CAREER_RACES.set(cfg.save_career_races);
     let _ = SAVE_TT_RACES.set(cfg.save_tt_races);
+    let _ = RACE_FILE_TEMPLATE.set(cfg.race_file_template);
+    let _ = TEAM_TRIALS_FILE_TEMPLATE.set(cfg.team_trials_file_template);
+    let _ = API_RESPONSE_FILE_TEMPLATE.set(cfg.api_response_file_template);
+    let _ = VETERANS_FILE_TEMPLATE.set(cfg.veterans_file_template);
+    let _ = FORCE_SAVE_DUPLICATES.set(cfg.force_save_duplicates);
+    let _ = SINKS.set(sinks);
+    let _ = OUTPUT_FORMATS.set(cfg.output_formats);
+    let _ = CSV_EXPORT.set(cfg.csv_export);
+    let _ = CSV_COLUMNS.set(cfg.csv_columns);
+    let _ = CONVERT_TO_PARQUET.set(convert_to_parquet);
+    let _ = RETENTION.set(cfg.retention);
+    let _ = RETENTION_INTERVAL_MINUTES.set(cfg.retention_interval_minutes);
+    let _ = SIM_DATA_SIDECAR.set(cfg.sim_data_sidecar);
+    let _ = HTML_REPORT.set(cfg.html_report);
+    let _ = GENERATE_HTML_REPORTS.set(generate_html_reports);
+    let _ = ENRICH_NAMES.set(cfg.enrich_names);
+    let _ = MASTER_DB_PATH.set(master_db_path);
+    let _ = JSON_SCHEMAS.set(cfg.json_schemas);
+    let _ = EXPORT_BUILDS.set(cfg.export_builds);
+    let _ = BUILDS_FAVOURITES_ONLY.set(cfg.builds_favourites_only);
+    let _ = BUILDS_MIN_RANK_SCORE.set(cfg.builds_min_rank_score);
+    let _ = SPLIT_TEAM_TRIALS.set(cfg.split_team_trials);
+    let _ = KEEP_TEAM_TRIALS_RESPONSE.set(cfg.keep_team_trials_response);
+    let _ = SAVE_RACE_TYPES.set(cfg.save_race_types);
+    let _ = CAPTURE_RULES.set(cfg.capture_rules);
+    // Entries missing from the config keep their built-in folder.
+    let mut race_type_folders = default_race_type_folders();
+    race_type_folders.extend(cfg.race_type_folders);
+    let _ = RACE_TYPE_FOLDERS.set(race_type_folders);
     Ok(())
 }
 
+/// Expands `%USERPROFILE%` and resolves relative paths against the plugin dir.
+fn resolve_path(p: &str, plugin_dir: &Path) -> PathBuf {
+    let path_str = p.trim();
+    let expanded_path = if let Ok(home) = env::var("USERPROFILE") {
+        path_str.replace("%USERPROFILE%", &home)
+    } else {
+        path_str.to_string()
+    };
+    let path = Path::new(&expanded_path);
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        plugin_dir.join(path)
+    }
+}
+
 #[macro_export]
 macro_rules! log {
     ($($arg:tt)*) => {
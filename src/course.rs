@@ -0,0 +1,103 @@
+use crate::lookup::{find_field, value_to_plain_string};
+use crate::master::{self, NameKind};
+use serde::Serialize;
+use serde_json::Value;
+
+// The course members live on RaceInfo itself or on the course set object it
+// references, so a couple of levels of nesting are searched.
+const COURSE_SEARCH_DEPTH: usize = 3;
+
+/// Course and track conditions of a race, collected from the RaceInfo dump so
+/// they can be grouped on without digging through the whole capture.
+#[derive(Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct CourseInfo {
+    pub race_type: Option<Value>,
+    pub race_instance_id: Option<Value>,
+    pub track_id: Option<Value>,
+    pub course_id: Option<Value>,
+    pub distance: Option<Value>,
+    pub surface: Option<Value>,
+    pub ground_condition: Option<Value>,
+    pub weather: Option<Value>,
+    pub season: Option<Value>,
+    /// Names resolved from the master database, when it is available.
+    pub race_name: Option<Value>,
+    pub track_name: Option<Value>,
+    pub course_name: Option<Value>,
+}
+
+impl CourseInfo {
+    /// `race_track_id` is the value returned by `RaceInfo.get_RaceTrackId`,
+    /// which is preferred over anything found in the dump.
+    pub fn from_race_info(race_info: &Value, race_track_id: i32) -> Self {
+        let field = |names: &[&str]| find_field(race_info, names, COURSE_SEARCH_DEPTH).cloned();
+
+        let track_id = if race_track_id != 0 {
+            Some(Value::from(race_track_id))
+        } else {
+            field(&["RaceTrackId", "TrackId"])
+        };
+
+        let race_instance_id = field(&["RaceInstanceId"]);
+        let course_id = field(&["RaceCourseSetId", "CourseSetId", "CourseId"]);
+        let name = |kind: NameKind, id: &Option<Value>| {
+            id.as_ref()
+                .and_then(|v| v.as_i64())
+                .and_then(|id| master::name(kind, id))
+                .map(Value::String)
+        };
+
+        CourseInfo {
+            race_type: field(&["RaceType"]),
+            race_name: name(NameKind::RaceInstance, &race_instance_id),
+            track_name: name(NameKind::Track, &track_id),
+            course_name: name(NameKind::Course, &course_id),
+            race_instance_id,
+            track_id,
+            course_id,
+            distance: field(&["Distance", "CourseDistance"]),
+            surface: field(&["GroundType", "Ground", "Surface"]).map(surface_name),
+            ground_condition: field(&["GroundCondition"]),
+            weather: field(&["Weather"]),
+            season: field(&["Season"]),
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+
+    /// Template placeholders exposed by the course block, as
+    /// `(placeholder, value)` pairs.
+    pub fn placeholders(&self) -> [(&'static str, String); 11] {
+        let s = |v: &Option<Value>| {
+            v.as_ref()
+                .map(value_to_plain_string)
+                .unwrap_or_else(|| "Unknown".to_string())
+        };
+        [
+            ("raceInstanceId", s(&self.race_instance_id)),
+            ("trackId", s(&self.track_id)),
+            ("courseId", s(&self.course_id)),
+            ("distance", s(&self.distance)),
+            ("surface", s(&self.surface)),
+            ("groundCondition", s(&self.ground_condition)),
+            ("weather", s(&self.weather)),
+            ("season", s(&self.season)),
+            ("raceName", s(&self.race_name)),
+            ("trackName", s(&self.track_name)),
+            ("courseName", s(&self.course_name)),
+        ]
+    }
+}
+
+// Master data stores the surface as 1 = turf, 2 = dirt; enums already arrive
+// as names from the dumper.
+fn surface_name(value: Value) -> Value {
+    match value.as_i64() {
+        Some(1) => Value::String("Turf".to_string()),
+        Some(2) => Value::String("Dirt".to_string()),
+        _ => value,
+    }
+}
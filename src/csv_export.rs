@@ -0,0 +1,187 @@
+use crate::config::{csv_columns, csv_export, CsvExport};
+use crate::log;
+use crate::lookup::{find_path, value_to_plain_string};
+use crate::persistence::write_atomic;
+use crate::template::sanitize_component;
+use serde_json::Value;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Rolling files are appended to from the game thread and from bulk tools.
+static CSV_LOCK: Mutex<()> = Mutex::new(());
+
+/// Writes the per-horse CSV rows for a race that was just saved to
+/// `saved_file` under the directory sink rooted at `root`. With `append`
+/// the per-race CSV is extended rather than replaced, for captures that share
+/// a file such as daily NDJSON.
+pub fn export_race(
+    root: &Path,
+    saved_file: &Path,
+    extension: &str,
+    append: bool,
+    race_type: Option<&str>,
+    output: &Value,
+    tag: &str,
+) {
+    let mode = csv_export();
+    if mode == CsvExport::Off {
+        return;
+    }
+
+    let columns = csv_columns();
+    let header = format_row(columns.iter().map(|c| c.as_str()));
+    let rows = race_rows(columns, output);
+
+    let result = match mode {
+        CsvExport::Off => return,
+        CsvExport::PerRace => {
+            let path = per_race_path(saved_file, extension);
+            if append {
+                append_rows(&path, &header, &rows)
+            } else {
+                write_rows(&path, &header, &rows)
+            }
+            .map(|()| path)
+        }
+        CsvExport::Rolling => {
+            let path = root.join(format!(
+                "results-{}.csv",
+                sanitize_component(race_type.unwrap_or("Unknown"))
+            ));
+            append_rows(&path, &header, &rows).map(|()| path)
+        }
+    };
+
+    match result {
+        Ok(path) => {
+            log!(
+                "[{}] Wrote {} CSV row(s) to: {}",
+                tag,
+                rows.len(),
+                path.display()
+            );
+        }
+        Err(e) => {
+            log!("[{}] Failed to write CSV: {}", tag, e);
+        }
+    }
+}
+
+fn per_race_path(saved_file: &Path, extension: &str) -> PathBuf {
+    let name = saved_file
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let stem = name
+        .strip_suffix(&format!(".{}", extension))
+        .unwrap_or(&name);
+    saved_file.with_file_name(format!("{}.csv", stem))
+}
+
+/// One row per entry of `<RaceHorse>k__BackingField`, in the game's order.
+fn race_rows(columns: &[String], output: &Value) -> Vec<String> {
+    let data = output.get("data").unwrap_or(output);
+    let horses = data
+        .get("<RaceHorse>k__BackingField")
+        .and_then(|v| v.as_array())
+        .map(|v| v.as_slice())
+        .unwrap_or(&[]);
+
+    horses
+        .iter()
+        .map(|horse| {
+            let cells: Vec<String> = columns
+                .iter()
+                .map(|c| cell(c, output, horse).unwrap_or_default())
+                .collect();
+            format_row(cells.iter().map(|c| c.as_str()))
+        })
+        .collect()
+}
+
+// `capture.` paths start at the envelope, `race.` at the course summary and
+// `horse.` (or no prefix) at the horse entry.
+fn cell(column: &str, output: &Value, horse: &Value) -> Option<String> {
+    let (root, path) = match column.split_once('.') {
+        Some(("capture", rest)) => (output, rest),
+        Some(("race", rest)) => (output.get("horseACT_race")?, rest),
+        Some(("horse", rest)) => (horse, rest),
+        _ => (horse, column),
+    };
+    match find_path(root, path)? {
+        Value::Null => None,
+        v => Some(value_to_plain_string(v)),
+    }
+}
+
+fn format_row<'a>(cells: impl Iterator<Item = &'a str>) -> String {
+    cells.map(escape).collect::<Vec<_>>().join(",")
+}
+
+fn escape(cell: &str) -> String {
+    if cell.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
+
+fn write_rows(path: &Path, header: &str, rows: &[String]) -> Result<(), String> {
+    let mut out = String::new();
+    for line in std::iter::once(header).chain(rows.iter().map(|r| r.as_str())) {
+        out.push_str(line);
+        out.push_str("\r\n");
+    }
+    write_atomic(path, out.as_bytes()).map_err(|e| e.to_string())
+}
+
+// Appends to an existing CSV. A file whose header no longer matches the
+// configured columns is moved aside first so every file stays rectangular.
+fn append_rows(path: &Path, header: &str, rows: &[String]) -> Result<(), String> {
+    let _guard = CSV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    // Only the header line is read; the rolling file grows with every race.
+    let existing_header = File::open(path).ok().and_then(|f| {
+        let mut first = String::new();
+        match BufReader::new(f).read_line(&mut first) {
+            Ok(n) if n > 0 => Some(first.trim_end_matches(['\r', '\n']).to_string()),
+            _ => None,
+        }
+    });
+    let needs_header = match existing_header {
+        None => true,
+        Some(h) if h == header => false,
+        Some(_) => {
+            let aside = path.with_extension(format!(
+                "{}.csv",
+                chrono::Local::now().format("%Y%m%d_%H%M%S")
+            ));
+            fs::rename(path, &aside).map_err(|e| format!("move old CSV aside: {}", e))?;
+            log!(
+                "[CSV] Columns changed, moved {} to {}",
+                path.display(),
+                aside.display()
+            );
+            true
+        }
+    };
+
+    let mut out = String::new();
+    if needs_header {
+        out.push_str(header);
+        out.push_str("\r\n");
+    }
+    for row in rows {
+        out.push_str(row);
+        out.push_str("\r\n");
+    }
+
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| e.to_string())?;
+    f.write_all(out.as_bytes()).map_err(|e| e.to_string())
+}
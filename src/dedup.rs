@@ -0,0 +1,130 @@
+use crate::config::save_root;
+use crate::log;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+const HASH_INDEX_FILE: &str = "race_hashes.jsonl";
+
+static KNOWN_HASHES: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+/// Hashes the parts of a race that identify it regardless of when or how
+/// often it was watched: the simulation payload and the horse entries.
+pub fn race_content_hash(race_info: &Value) -> String {
+    let mut canonical = Map::new();
+    for key in [
+        "<SimDataBase64>k__BackingField",
+        "<RaceHorse>k__BackingField",
+    ] {
+        if let Some(v) = race_info.get(key) {
+            canonical.insert(key.to_string(), canonicalize(v));
+        }
+    }
+
+    let bytes = serde_json::to_vec(&Value::Object(canonical)).unwrap_or_default();
+    hex::encode(Sha256::digest(&bytes))
+}
+
+/// Hashes any captured value, ignoring member order, cycle markers and
+/// horseACT's annotations.
+pub fn content_hash(value: &Value) -> String {
+    let bytes = serde_json::to_vec(&canonicalize(value)).unwrap_or_default();
+    hex::encode(Sha256::digest(&bytes))
+}
+
+// Sorts object keys and drops cycle markers, which embed object addresses
+// that differ between sessions, and horseACT's own annotations.
+fn canonicalize(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            let mut out = Map::new();
+            for key in keys {
+                let v = &map[key];
+                if key.starts_with("horseACT_")
+                    || v.as_str().is_some_and(|s| s.starts_with("<Cycle:"))
+                {
+                    continue;
+                }
+                out.insert(key.clone(), canonicalize(v));
+            }
+            Value::Object(out)
+        }
+        Value::Array(arr) => Value::Array(arr.iter().map(canonicalize).collect()),
+        other => other.clone(),
+    }
+}
+
+fn index_path() -> PathBuf {
+    save_root().join(HASH_INDEX_FILE)
+}
+
+fn with_known_hashes<R>(f: impl FnOnce(&mut HashSet<String>) -> R) -> R {
+    let mut guard = KNOWN_HASHES.lock().unwrap_or_else(|e| e.into_inner());
+    let known = guard.get_or_insert_with(load_known_hashes);
+    f(known)
+}
+
+fn load_known_hashes() -> HashSet<String> {
+    let mut known = HashSet::new();
+    let Ok(contents) = fs::read_to_string(index_path()) else {
+        return known;
+    };
+    // A line cut short by a crash simply fails to parse and is ignored.
+    for line in contents.lines() {
+        if let Ok(entry) = serde_json::from_str::<Value>(line) {
+            if let Some(hash) = entry.get("hash").and_then(|v| v.as_str()) {
+                known.insert(hash.to_string());
+            }
+        }
+    }
+    log!("[Dedup] Loaded {} known race hash(es)", known.len());
+    known
+}
+
+pub fn is_known(hash: &str) -> bool {
+    with_known_hashes(|known| known.contains(hash))
+}
+
+/// Records a newly saved race. `location` is the file path relative to the
+/// save root (with `#L<line>` appended for NDJSON files), or `sqlite:<id>`
+/// for races stored in the database.
+pub fn record_saved(hash: &str, location: &str) {
+    with_known_hashes(|known| {
+        known.insert(hash.to_string());
+    });
+    append_entry(json!({
+        "hash": hash,
+        "event": "saved",
+        "location": location,
+        "at": chrono::Local::now().to_rfc3339(),
+    }));
+}
+
+/// Records that an already known race was captured again.
+pub fn record_resighting(hash: &str) {
+    append_entry(json!({
+        "hash": hash,
+        "event": "resighting",
+        "at": chrono::Local::now().to_rfc3339(),
+    }));
+}
+
+fn append_entry(entry: Value) {
+    let path = index_path();
+    match OpenOptions::new().create(true).append(true).open(&path) {
+        Ok(mut f) => {
+            if let Err(e) = writeln!(f, "{}", entry) {
+                log!("[Dedup] Failed to append to {}: {}", path.display(), e);
+            }
+        }
+        Err(e) => {
+            log!("[Dedup] Failed to open {}: {}", path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use crate::config::field_blacklist;
+use crate::log;
+use crate::ownership::owner_id_fields;
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::Read;
+use std::sync::OnceLock;
+
+/// Bumped whenever the layout of the envelope or of `data` changes in a way
+/// importers have to care about.
+pub const SCHEMA_VERSION: u32 = 1;
+
+static GAME_ASSEMBLY_FINGERPRINT: OnceLock<String> = OnceLock::new();
+static RACE_HOOK_TARGET: OnceLock<String> = OnceLock::new();
+static TEAM_TRIALS_HOOK_TARGET: OnceLock<String> = OnceLock::new();
+static VETERANS_HOOK_TARGET: OnceLock<String> = OnceLock::new();
+// Endpoint hooks sit on a different method per endpoint; their envelope names
+// the endpoint instead.
+static API_RESPONSE_HOOK_TARGET: OnceLock<String> = OnceLock::new();
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CaptureType {
+    Race,
+    TeamTrials,
+    Veterans,
+    /// A response captured by one of the endpoint hooks.
+    ApiResponse,
+}
+
+impl CaptureType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CaptureType::Race => "race",
+            CaptureType::TeamTrials => "teamTrials",
+            CaptureType::Veterans => "veterans",
+            CaptureType::ApiResponse => "apiResponse",
+        }
+    }
+
+    fn hook_target_cell(&self) -> &'static OnceLock<String> {
+        match self {
+            CaptureType::Race => &RACE_HOOK_TARGET,
+            CaptureType::TeamTrials => &TEAM_TRIALS_HOOK_TARGET,
+            CaptureType::Veterans => &VETERANS_HOOK_TARGET,
+            CaptureType::ApiResponse => &API_RESPONSE_HOOK_TARGET,
+        }
+    }
+}
+
+pub fn set_hook_target(capture_type: CaptureType, target: String) {
+    let _ = capture_type.hook_target_cell().set(target);
+}
+
+/// Hashes GameAssembly.dll so captures can be tied to the game build they came
+/// from. Called once from the hook installation thread since the file is large.
+pub fn init_game_assembly_fingerprint() {
+    let path = std::env::current_exe()
+        .ok()
+        .and_then(|p| p.parent().map(|d| d.join("GameAssembly.dll")));
+
+    let fingerprint = match path.map(|p| hash_file(&p)) {
+        Some(Ok(hash)) => format!("sha256:{}", hash),
+        Some(Err(e)) => {
+            log!("Failed to fingerprint GameAssembly.dll: {}", e);
+            "unknown".to_string()
+        }
+        None => "unknown".to_string(),
+    };
+    log!("Game assembly fingerprint: {}", fingerprint);
+    let _ = GAME_ASSEMBLY_FINGERPRINT.set(fingerprint);
+}
+
+pub fn game_assembly_fingerprint() -> &'static str {
+    GAME_ASSEMBLY_FINGERPRINT
+        .get()
+        .map(|s| s.as_str())
+        .unwrap_or("unknown")
+}
+
+fn hash_file(path: &std::path::Path) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Wraps a captured payload in the versioned envelope every output file uses.
+/// `metadata` entries are placed between the capture information and `data`.
+pub fn wrap(
+    capture_type: CaptureType,
+    captured_at: DateTime<Local>,
+    metadata: Vec<(&str, Value)>,
+    data: Value,
+) -> Value {
+    let mut map = Map::new();
+    map.insert("schemaVersion".to_string(), Value::from(SCHEMA_VERSION));
+    map.insert(
+        "pluginVersion".to_string(),
+        Value::String(env!("CARGO_PKG_VERSION").to_string()),
+    );
+    map.insert(
+        "captureType".to_string(),
+        Value::String(capture_type.as_str().to_string()),
+    );
+
+    let mut captured = Map::new();
+    captured.insert(
+        "utc".to_string(),
+        Value::String(
+            captured_at
+                .with_timezone(&Utc)
+                .to_rfc3339_opts(SecondsFormat::Millis, true),
+        ),
+    );
+    captured.insert(
+        "local".to_string(),
+        Value::String(captured_at.to_rfc3339_opts(SecondsFormat::Millis, false)),
+    );
+    map.insert("capturedAt".to_string(), Value::Object(captured));
+
+    map.insert(
+        "gameAssemblyFingerprint".to_string(),
+        Value::String(game_assembly_fingerprint().to_string()),
+    );
+    map.insert(
+        "hookTarget".to_string(),
+        capture_type
+            .hook_target_cell()
+            .get()
+            .map(|s| Value::String(s.clone()))
+            .unwrap_or(Value::Null),
+    );
+    map.insert("rules".to_string(), rules(capture_type));
+
+    for (key, value) in metadata {
+        map.insert(key.to_string(), value);
+    }
+
+    map.insert("data".to_string(), data);
+    Value::Object(map)
+}
+
+fn rules(capture_type: CaptureType) -> Value {
+    let mut rules = Map::new();
+    rules.insert(
+        "fieldBlacklist".to_string(),
+        Value::from(field_blacklist().clone()),
+    );
+    // Owner ids are only read from races, to mark the local player's horses.
+    let pseudonymized = match capture_type {
+        CaptureType::Race => owner_id_fields(),
+        CaptureType::TeamTrials | CaptureType::Veterans | CaptureType::ApiResponse => Vec::new(),
+    };
+    rules.insert(
+        "pseudonymizedFields".to_string(),
+        Value::from(pseudonymized),
+    );
+    Value::Object(rules)
+}
@@ -0,0 +1,192 @@
+use crate::config::OutputFormat;
+use crate::template::strip_extension;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Size and line count of the NDJSON files appended to this session, so a
+// daily file is not read back on every capture.
+static LINE_COUNTS: Mutex<Option<HashMap<PathBuf, (u64, usize)>>> = Mutex::new(None);
+
+// Ordered so that `.json.gz` is matched before `.json`.
+const EXTENSIONS: [(&str, OutputFormat); 4] = [
+    ("json.gz", OutputFormat::Gzip),
+    ("json", OutputFormat::Pretty),
+    ("ndjson", OutputFormat::Ndjson),
+    ("msgpack", OutputFormat::Msgpack),
+];
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Pretty | OutputFormat::Compact => "json",
+            OutputFormat::Gzip => "json.gz",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Msgpack => "msgpack",
+        }
+    }
+
+    /// Detects the format of a saved file from its extension. Pretty and
+    /// compact JSON read the same, so `.json` always maps to `Pretty`.
+    pub fn from_path(path: &Path) -> Option<OutputFormat> {
+        let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+        EXTENSIONS
+            .iter()
+            .find(|(ext, _)| name.ends_with(&format!(".{}", ext)))
+            .map(|(_, format)| *format)
+    }
+}
+
+/// Path that files derived from a saved race (simulation sidecars, reports)
+/// are named after: the race file without its extension, plus `-L<line>` for
+/// races sharing an NDJSON file.
+pub fn sidecar_base(saved_file: &Path, format: OutputFormat, line: Option<usize>) -> PathBuf {
+    let mut base = strip_extension(saved_file.to_path_buf(), format.extension()).into_os_string();
+    if let Some(line) = line {
+        base.push(format!("-L{}", line));
+    }
+    PathBuf::from(base)
+}
+
+/// Suffixes of the files derived from a saved race, added to its
+/// `sidecar_base`: the per-race CSV, the HTML report and the simulation
+/// sidecars.
+pub const SIDECAR_SUFFIXES: [&str; 5] = [".csv", ".html", ".sim.json", ".sim.csv", ".timeline.csv"];
+
+/// Whether a file is a sidecar that would otherwise pass for a capture, such
+/// as `<race>.sim.json`.
+pub fn is_sidecar(path: &Path) -> bool {
+    path.file_name().is_some_and(|n| {
+        n.to_string_lossy()
+            .to_ascii_lowercase()
+            .ends_with(".sim.json")
+    })
+}
+
+/// The sidecars present next to a saved file, for every race it holds.
+pub fn sidecars(saved_file: &Path, format: OutputFormat) -> Vec<PathBuf> {
+    let base = sidecar_base(saved_file, format, None);
+    let (Some(dir), Some(stem)) = (base.parent(), base.file_name()) else {
+        return Vec::new();
+    };
+    let stem = stem.to_string_lossy().to_string();
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let Some(rest) = name.strip_prefix(&stem) else {
+                return false;
+            };
+            // NDJSON races are told apart by `-L<line>`.
+            let rest = match rest.strip_prefix("-L") {
+                Some(line) if format == OutputFormat::Ndjson => {
+                    line.trim_start_matches(|c: char| c.is_ascii_digit())
+                }
+                _ => rest,
+            };
+            SIDECAR_SUFFIXES.contains(&rest)
+        })
+        .collect()
+}
+
+/// Serializes a capture as a whole file. NDJSON is encoded as a single line
+/// and is expected to be appended with [`append_line`].
+pub fn encode(format: OutputFormat, value: &Value) -> Result<Vec<u8>, String> {
+    match format {
+        OutputFormat::Pretty => serde_json::to_vec_pretty(value).map_err(|e| e.to_string()),
+        OutputFormat::Compact => serde_json::to_vec(value).map_err(|e| e.to_string()),
+        OutputFormat::Ndjson => {
+            let mut line = serde_json::to_vec(value).map_err(|e| e.to_string())?;
+            line.push(b'\n');
+            Ok(line)
+        }
+        OutputFormat::Gzip => {
+            let json = serde_json::to_vec(value).map_err(|e| e.to_string())?;
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&json).map_err(|e| e.to_string())?;
+            encoder.finish().map_err(|e| e.to_string())
+        }
+        OutputFormat::Msgpack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+    }
+}
+
+/// Appends one capture to an NDJSON file and returns its 1-based line number.
+pub fn append_line(path: &Path, value: &Value) -> Result<usize, String> {
+    let line = encode(OutputFormat::Ndjson, value)?;
+
+    let mut guard = LINE_COUNTS.lock().unwrap_or_else(|e| e.into_inner());
+    let counts = guard.get_or_insert_with(HashMap::new);
+    let len = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    // The file is only counted again when something else changed it, e.g.
+    // retention archived it.
+    let existing_lines = match counts.get(path) {
+        Some(&(known_len, lines)) if known_len == len => lines,
+        _ => match fs::read(path) {
+            Ok(bytes) => bytes.iter().filter(|b| **b == b'\n').count(),
+            Err(_) => 0,
+        },
+    };
+
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| e.to_string())?;
+    f.write_all(&line).map_err(|e| e.to_string())?;
+    f.sync_all().map_err(|e| e.to_string())?;
+    counts.insert(
+        path.to_path_buf(),
+        (len + line.len() as u64, existing_lines + 1),
+    );
+    Ok(existing_lines + 1)
+}
+
+/// Reads every capture stored in a saved file, whatever its format, paired
+/// with its line number for NDJSON files. Unparsable NDJSON lines are skipped.
+pub fn read_captures(path: &Path) -> Result<Vec<(Option<usize>, Value)>, String> {
+    let format =
+        OutputFormat::from_path(path).ok_or_else(|| "unknown file extension".to_string())?;
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    decode_captures(format, &bytes)
+}
+
+/// Same as [`read_captures`] for a file that has already been read.
+pub fn decode_captures(
+    format: OutputFormat,
+    bytes: &[u8],
+) -> Result<Vec<(Option<usize>, Value)>, String> {
+    let whole: Value = match format {
+        OutputFormat::Pretty | OutputFormat::Compact => {
+            serde_json::from_slice(bytes).map_err(|e| e.to_string())?
+        }
+        OutputFormat::Gzip => {
+            let mut json = Vec::new();
+            MultiGzDecoder::new(bytes)
+                .read_to_end(&mut json)
+                .map_err(|e| e.to_string())?;
+            serde_json::from_slice(&json).map_err(|e| e.to_string())?
+        }
+        OutputFormat::Msgpack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string())?,
+        OutputFormat::Ndjson => {
+            return Ok(String::from_utf8_lossy(bytes)
+                .lines()
+                .enumerate()
+                .filter_map(|(i, l)| serde_json::from_str(l).ok().map(|v| (Some(i + 1), v)))
+                .collect());
+        }
+    };
+    Ok(vec![(None, whole)])
+}
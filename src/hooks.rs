@@ -5,12 +5,16 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::il2cpp::*;
 use crate::log;
-use crate::persistence::{save_race_info, save_team_trial_result, save_veteran_data};
+use crate::ownership::{mark_owners, owner_id_fields};
+use crate::persistence::{
+    save_api_response, save_race_info, save_team_trial_result, save_veteran_data,
+};
 use crate::reflection::convert_object_to_value;
 
 pub static mut ORIG_GET_RACE_TRACK_ID: usize = 0;
 pub static mut ORIG_VETERAN_APPLY: usize = 0;
 pub static mut ORIG_TEAM_STADIUM_RESULT: usize = 0;
+pub static mut VIEWER_ID_GETTER: usize = 0;
 
 pub const MAX_API_HOOKS: usize = 8;
 pub static mut API_HOOK_ORIGS: [usize; MAX_API_HOOKS] = [0; MAX_API_HOOKS];
@@ -41,7 +45,7 @@ macro_rules! api_hook_slot {
                 &mut visited,
                 &endpoint_config.sensitive_fields,
             );
-            crate::api::dispatch(&endpoint_config.name, val);
+            save_api_response(&endpoint_config.name, &val);
         }
     };
 }
@@ -74,6 +78,15 @@ pub unsafe extern "C" fn race_info_hook(
     this: *mut RawIl2CppObject,
     method: *const RawMethodInfo,
 ) -> i32 {
+    // Let the game compute the track id first so it can be recorded with the dump.
+    let race_track_id = if ORIG_GET_RACE_TRACK_ID != 0 {
+        let orig: extern "C" fn(*mut RawIl2CppObject, *const RawMethodInfo) -> i32 =
+            transmute(ORIG_GET_RACE_TRACK_ID);
+        orig(this, method)
+    } else {
+        0
+    };
+
     let current_addr = this as usize;
     let last_addr = LAST_DUMPED_PTR.load(Ordering::SeqCst);
     let mut current_sim_ptr: usize = 0;
@@ -161,8 +174,10 @@ pub unsafe extern "C" fn race_info_hook(
                             log!("[RaceInfo] Dumping valid race data...");
 
                             let mut visited = HashSet::new();
-                            let val = convert_object_to_value(this, 0, &mut visited, &[]);
-                            save_race_info(val);
+                            let mut val =
+                                convert_object_to_value(this, 0, &mut visited, &owner_id_fields());
+                            mark_owners(&mut val, local_viewer_id());
+                            save_race_info(val, race_track_id);
 
                             log!("[RaceInfo] Dump Complete.");
                         } else {
@@ -178,13 +193,16 @@ pub unsafe extern "C" fn race_info_hook(
         }
     }
 
-    if ORIG_GET_RACE_TRACK_ID != 0 {
-        let orig: extern "C" fn(*mut RawIl2CppObject, *const RawMethodInfo) -> i32 =
-            transmute(ORIG_GET_RACE_TRACK_ID);
-        return orig(this, method);
-    }
+    race_track_id
+}
 
-    0
+/// Viewer id of the logged in player, from `Gallop.Certification.get_ViewerId`.
+unsafe fn local_viewer_id() -> Option<i64> {
+    if VIEWER_ID_GETTER == 0 {
+        return None;
+    }
+    let getter: unsafe extern "C" fn(*const RawMethodInfo) -> i64 = transmute(VIEWER_ID_GETTER);
+    Some(getter(std::ptr::null())).filter(|id| *id != 0)
 }
 
 pub unsafe extern "C" fn team_stadium_result_hook(
@@ -0,0 +1,321 @@
+use crate::builds::BUILDS_DIR;
+use crate::config::{relative_to_save_root, save_root, OutputFormat};
+use crate::course::CourseInfo;
+use crate::dedup::race_content_hash;
+use crate::formats::{is_sidecar, read_captures};
+use crate::log;
+use crate::lookup::find_field;
+use crate::persistence::{race_winner, write_atomic};
+use crate::retention::{for_each_archived, ARCHIVE_DIR};
+use crate::schema::SCHEMA_DIR;
+use crate::veteran_history::VETERAN_HISTORY_DIR;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const INDEX_FILE: &str = "index.jsonl";
+
+// Files in the save root that are bookkeeping rather than captures.
+const NON_CAPTURE_FILES: [&str; 2] = [INDEX_FILE, "race_hashes.jsonl"];
+
+static INDEX_LOCK: Mutex<()> = Mutex::new(());
+
+fn index_path() -> PathBuf {
+    save_root().join(INDEX_FILE)
+}
+
+// The index only describes the save root, which is what it is rebuilt from
+// and what retention archives. Directory sinks with their own `path` are not
+// indexed.
+fn indexed(path: &Path) -> bool {
+    path.starts_with(save_root())
+}
+
+/// Appends the row for a freshly written file. Rows are never rewritten in
+/// place; a later row for the same path supersedes earlier ones.
+///
+/// `line` is set for captures appended to an NDJSON file; rows are then
+/// identified by path and line together.
+pub fn append(path: &Path, line: Option<usize>, output: &Value) {
+    if indexed(path) {
+        append_row(row_for(path, line, output, None));
+    }
+}
+
+/// Appends a superseding row for `path` with a new upload status.
+pub fn append_with_status(path: &Path, line: Option<usize>, output: &Value, status: &str) {
+    if !indexed(path) {
+        return;
+    }
+    let mut row = row_for(path, line, output, None);
+    if let Value::Object(ref mut map) = row {
+        map.insert(
+            "uploadStatus".to_string(),
+            Value::String(status.to_string()),
+        );
+    }
+    append_row(row);
+}
+
+/// Marks the captures of a file as moved into an archive bundle. The latest
+/// row of each capture is carried over, so its upload status survives, with
+/// `archive` set to the bundle path.
+pub fn append_archived(path: &Path, captures: &[(Option<usize>, Value)], bundle: &str) {
+    let relative = relative_to_save_root(path);
+    let mut latest: HashMap<Option<u64>, Value> = HashMap::new();
+    if let Ok(contents) = fs::read_to_string(index_path()) {
+        for row in contents
+            .lines()
+            .filter_map(|l| serde_json::from_str::<Value>(l).ok())
+            .filter(|row| row.get("path").and_then(|p| p.as_str()) == Some(relative.as_str()))
+        {
+            latest.insert(row.get("line").and_then(|l| l.as_u64()), row);
+        }
+    }
+
+    for (line, output) in captures {
+        let mut row = latest
+            .remove(&line.map(|l| l as u64))
+            .unwrap_or_else(|| row_for(path, *line, output, None));
+        if let Value::Object(ref mut map) = row {
+            map.insert("archive".to_string(), Value::String(bundle.to_string()));
+        }
+        append_row(row);
+    }
+}
+
+fn append_row(row: Value) {
+    let _guard = INDEX_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let index = index_path();
+    match OpenOptions::new().create(true).append(true).open(&index) {
+        Ok(mut f) => {
+            if let Err(e) = writeln!(f, "{}", row) {
+                log!("[Index] Failed to append to {}: {}", index.display(), e);
+            }
+        }
+        Err(e) => {
+            log!("[Index] Failed to open {}: {}", index.display(), e);
+        }
+    }
+}
+
+/// Rebuilds the index from the files on disk when it is missing or contains
+/// rows that do not parse. Meant to run once at startup off the game thread.
+pub fn ensure_index() {
+    let healthy = match fs::read_to_string(index_path()) {
+        Ok(contents) => contents
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .all(|l| serde_json::from_str::<Value>(l).is_ok_and(|v| v.is_object())),
+        Err(_) => false,
+    };
+
+    if !healthy {
+        log!("[Index] Index missing or corrupted, rebuilding from saved files.");
+        rebuild();
+    }
+}
+
+pub fn rebuild() {
+    let _guard = INDEX_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let files = capture_files();
+
+    let mut rows: Vec<Value> = Vec::new();
+    for path in &files {
+        let Ok(captures) = read_captures(path) else {
+            continue;
+        };
+        let modified = fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()
+            .map(|t| chrono::DateTime::<chrono::Utc>::from(t).to_rfc3339());
+        for (line, value) in captures {
+            rows.push(row_for(path, line, &value, modified.clone()));
+        }
+    }
+    for_each_archived(|entry, bundle, modified, captures| {
+        let path = save_root().join(entry);
+        for (line, value) in captures {
+            let mut row = row_for(&path, line, &value, modified.clone());
+            if let Value::Object(ref mut map) = row {
+                map.insert("archive".to_string(), Value::String(bundle.to_string()));
+            }
+            rows.push(row);
+        }
+    });
+
+    rows.sort_by(|a, b| {
+        let key = |v: &Value| {
+            v.get("capturedAt")
+                .and_then(|t| t.as_str())
+                .unwrap_or("")
+                .to_string()
+        };
+        key(a).cmp(&key(b))
+    });
+
+    let mut out = String::new();
+    for row in &rows {
+        out.push_str(&row.to_string());
+        out.push('\n');
+    }
+
+    match write_atomic(&index_path(), out.as_bytes()) {
+        Ok(()) => {
+            log!("[Index] Rebuilt index with {} row(s).", rows.len());
+        }
+        Err(e) => {
+            log!("[Index] Failed to write rebuilt index: {}", e);
+        }
+    }
+}
+
+/// Every saved capture file under the save root, in any output format.
+pub fn capture_files() -> Vec<PathBuf> {
+    capture_files_in(save_root())
+}
+
+/// Capture files under `dir`, skipping the retention archive.
+pub fn capture_files_in(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    collect_capture_files(dir, &mut files);
+    files
+}
+
+fn collect_capture_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            if [ARCHIVE_DIR, BUILDS_DIR, SCHEMA_DIR, VETERAN_HISTORY_DIR]
+                .iter()
+                .any(|d| path == save_root().join(d))
+            {
+                continue;
+            }
+            collect_capture_files(&path, files);
+        } else if OutputFormat::from_path(&path).is_some()
+            && !is_sidecar(&path)
+            && !NON_CAPTURE_FILES.contains(&name.as_str())
+        {
+            files.push(path);
+        }
+    }
+}
+
+/// Builds an index row from a saved file. Files written before the output
+/// envelope existed are indexed from their raw contents; `fallback_time` is
+/// used for them since they carry no capture time.
+fn row_for(
+    path: &Path,
+    line: Option<usize>,
+    output: &Value,
+    fallback_time: Option<String>,
+) -> Value {
+    let enveloped = output.get("schemaVersion").is_some() && output.get("data").is_some();
+    let data = if enveloped { &output["data"] } else { output };
+
+    let capture_type = if enveloped {
+        output
+            .get("captureType")
+            .and_then(|v| v.as_str())
+            .unwrap_or("unknown")
+            .to_string()
+    } else if data.is_array() {
+        "veterans".to_string()
+    } else if data.get("<RaceHorse>k__BackingField").is_some() {
+        "race".to_string()
+    } else if find_field(data, &["race_result_array"], 3).is_some() {
+        "teamTrials".to_string()
+    } else {
+        "unknown".to_string()
+    };
+
+    let mut row = Map::new();
+    row.insert(
+        "path".to_string(),
+        Value::String(relative_to_save_root(path)),
+    );
+    if let Some(line) = line {
+        row.insert("line".to_string(), Value::from(line));
+    }
+    row.insert(
+        "captureType".to_string(),
+        Value::String(capture_type.clone()),
+    );
+    row.insert(
+        "capturedAt".to_string(),
+        output
+            .get("capturedAt")
+            .and_then(|v| v.get("utc"))
+            .cloned()
+            .or_else(|| fallback_time.map(Value::String))
+            .unwrap_or(Value::Null),
+    );
+
+    if capture_type == "race" {
+        let course = output
+            .get("horseACT_race")
+            .cloned()
+            .unwrap_or_else(|| CourseInfo::from_race_info(data, 0).to_value());
+        if let Value::Object(course) = course {
+            row.extend(course);
+        }
+
+        let (winner, finish_time) = match race_winner(data) {
+            Some((name, time)) => (Value::String(name), Value::from(time)),
+            None => (Value::Null, Value::Null),
+        };
+        row.insert("winner".to_string(), winner);
+        row.insert("finishTime".to_string(), finish_time);
+
+        let chara_ids: Vec<Value> = data
+            .get("<RaceHorse>k__BackingField")
+            .and_then(|v| v.as_array())
+            .map(|horses| {
+                horses
+                    .iter()
+                    .map(|h| {
+                        find_field(h, &["charaId"], 2)
+                            .cloned()
+                            .unwrap_or(Value::Null)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        row.insert("charaIds".to_string(), Value::Array(chara_ids));
+
+        row.insert(
+            "contentHash".to_string(),
+            output
+                .get("contentHash")
+                .cloned()
+                .unwrap_or_else(|| Value::String(race_content_hash(data))),
+        );
+    }
+
+    row.insert(
+        "horseACTVersion".to_string(),
+        output
+            .get("pluginVersion")
+            .or_else(|| data.get("horseACT_version"))
+            .cloned()
+            .unwrap_or(Value::Null),
+    );
+    row.insert(
+        "uploadStatus".to_string(),
+        Value::String("local".to_string()),
+    );
+
+    Value::Object(row)
+}
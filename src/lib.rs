@@ -1,17 +1,43 @@
 #![allow(non_snake_case)]
 
 mod api;
+mod builds;
+mod capture_rules;
 mod config;
+mod course;
+mod csv_export;
+mod dedup;
+mod envelope;
+mod formats;
 mod hooks;
 mod il2cpp;
+mod index;
+mod lookup;
+mod master;
+mod models;
+mod ownership;
+mod parquet_export;
 mod persistence;
 mod plugin_api;
 mod reflection;
+mod report;
+mod retention;
+mod schema;
+mod simdata;
+mod sinks;
+mod sqlite;
+mod summary;
+mod team_trials;
+mod template;
+mod timeline;
+mod veteran_history;
 
-use crate::config::init_paths;
+use crate::config::{convert_to_parquet, generate_html_reports, init_paths};
+use crate::envelope::{init_game_assembly_fingerprint, set_hook_target, CaptureType};
 use crate::hooks::{
     race_info_hook, team_stadium_result_hook, veteran_hook, API_HOOK_FNS, API_HOOK_ORIGS,
     MAX_API_HOOKS, ORIG_GET_RACE_TRACK_ID, ORIG_TEAM_STADIUM_RESULT, ORIG_VETERAN_APPLY,
+    VIEWER_ID_GETTER,
 };
 use crate::il2cpp::{
     find_image_by_name, find_method_addr_by_name, get_class_from_image, init_il2cpp_methods,
@@ -37,6 +63,19 @@ fn init() -> InitResult {
         return InitResult::Error;
     }
 
+    // Retention runs after the index check so both never scan the save
+    // root at the same time.
+    std::thread::spawn(|| {
+        index::ensure_index();
+        retention::enforce_periodically();
+    });
+    if convert_to_parquet() {
+        std::thread::spawn(|| parquet_export::convert_all(&parquet_export::bulk_dir()));
+    }
+    if generate_html_reports() {
+        std::thread::spawn(report::generate_all);
+    }
+
     let vt = vtable();
 
     unsafe {
@@ -54,6 +93,8 @@ fn init() -> InitResult {
 }
 
 unsafe fn install_hooks() {
+    init_game_assembly_fingerprint();
+
     let mut target_image = find_image_by_name("umamusume");
     if target_image.is_null() {
         target_image = find_image_by_name("Assembly-CSharp");
@@ -74,6 +115,10 @@ unsafe fn install_hooks() {
         if fn_ptr != 0 {
             if let Some(orig) = hook(fn_ptr, race_info_hook as *const () as usize) {
                 ORIG_GET_RACE_TRACK_ID = orig;
+                set_hook_target(
+                    CaptureType::Race,
+                    "Gallop.RaceInfo.get_RaceTrackId".to_string(),
+                );
                 log!("Hooked: Gallop.RaceInfo.get_RaceTrackId");
             }
         } else {
@@ -83,6 +128,15 @@ unsafe fn install_hooks() {
         log!("Failed to find RaceInfo class");
     }
 
+    let certification_class =
+        get_class_from_image(target_image, c"Gallop".as_ptr(), c"Certification".as_ptr());
+    VIEWER_ID_GETTER = find_method_addr_by_name(certification_class, c"get_ViewerId".as_ptr(), 0);
+    if VIEWER_ID_GETTER != 0 {
+        log!("Found Gallop.Certification.get_ViewerId");
+    } else {
+        log!("Failed to find get_ViewerId; own horses will not be marked");
+    }
+
     let results =
         find_methods_in_assembly_by_param(target_image as *mut RawIl2CppImage, "TrainedChara[]");
     if results.is_empty() {
@@ -100,6 +154,10 @@ unsafe fn install_hooks() {
             if fn_ptr != 0 {
                 if let Some(orig) = hook(fn_ptr, veteran_hook as *const () as usize) {
                     ORIG_VETERAN_APPLY = orig;
+                    set_hook_target(
+                        CaptureType::Veterans,
+                        format!("{}.{}", result.class_name, result.method_name),
+                    );
                     log!(
                         "Veteran hook installed on {}.{}",
                         result.class_name,
@@ -144,6 +202,10 @@ unsafe fn install_hooks() {
             if fn_ptr != 0 {
                 if let Some(orig) = hook(fn_ptr, team_stadium_result_hook as *const () as usize) {
                     ORIG_TEAM_STADIUM_RESULT = orig;
+                    set_hook_target(
+                        CaptureType::TeamTrials,
+                        format!("{}.{}", result.class_name, result.method_name),
+                    );
                     log!(
                         "TeamTrials hook installed on {}.{}",
                         result.class_name,
@@ -0,0 +1,100 @@
+use serde_json::{Map, Value};
+use std::collections::VecDeque;
+
+/// Reduces the different spellings the dumper produces for the same member
+/// (`<RaceType>k__BackingField`, `_raceType`, `race_type`, `RaceType`) to a
+/// single comparable form.
+pub fn normalize_key(key: &str) -> String {
+    let key = key
+        .strip_prefix('<')
+        .and_then(|k| k.strip_suffix(">k__BackingField"))
+        .unwrap_or(key);
+    key.chars()
+        .filter(|c| *c != '_')
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+/// Finds the first field named like one of `names`, breadth-first through
+/// nested objects up to `max_depth` levels. Arrays are not descended into so
+/// that per-horse fields never shadow race-level ones.
+pub fn find_field<'a>(value: &'a Value, names: &[&str], max_depth: usize) -> Option<&'a Value> {
+    let wanted: Vec<String> = names.iter().map(|n| normalize_key(n)).collect();
+    let mut queue = VecDeque::new();
+    queue.push_back((value, 0));
+
+    while let Some((current, depth)) = queue.pop_front() {
+        let Value::Object(map) = current else {
+            continue;
+        };
+        for name in &wanted {
+            if let Some((_, v)) = map
+                .iter()
+                .find(|(k, v)| !v.is_null() && normalize_key(k) == *name)
+            {
+                return Some(v);
+            }
+        }
+        if depth < max_depth {
+            for v in map.values() {
+                if v.is_object() {
+                    queue.push_back((v, depth + 1));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Collects the fields of `value` and of the objects nested in it up to
+/// `max_depth` levels into one object keyed by normalized name, so that a
+/// typed view can be deserialized from it. As with `find_field`, shallower
+/// fields win and nulls are left out.
+pub fn flatten_fields(value: &Value, max_depth: usize) -> Value {
+    let mut fields = Map::new();
+    let mut queue = VecDeque::new();
+    queue.push_back((value, 0));
+
+    while let Some((current, depth)) = queue.pop_front() {
+        let Value::Object(map) = current else {
+            continue;
+        };
+        for (k, v) in map {
+            if !v.is_null() {
+                fields.entry(normalize_key(k)).or_insert_with(|| v.clone());
+            }
+        }
+        if depth < max_depth {
+            for v in map.values() {
+                if v.is_object() {
+                    queue.push_back((v, depth + 1));
+                }
+            }
+        }
+    }
+    Value::Object(fields)
+}
+
+/// Follows a dot-separated path such as `horse.charaName` or `data.0.id`.
+/// Object keys are compared in normalized form, numeric segments index into
+/// arrays.
+pub fn find_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(value, |current, segment| match current {
+            Value::Array(arr) => arr.get(segment.parse::<usize>().ok()?),
+            Value::Object(map) => {
+                let wanted = normalize_key(segment);
+                map.iter()
+                    .find(|(k, _)| normalize_key(k) == wanted)
+                    .map(|(_, v)| v)
+            }
+            _ => None,
+        })
+}
+
+pub fn value_to_plain_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
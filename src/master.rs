@@ -0,0 +1,196 @@
+use crate::config::{enrich_names, master_db_path};
+use crate::log;
+use crate::lookup::normalize_key;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+// `text_data` categories of the master database used for names.
+const TEXT_CARD: i64 = 4;
+const TEXT_CHARA: i64 = 6;
+const TEXT_RACE: i64 = 28;
+const TEXT_TRACK: i64 = 31;
+const TEXT_SKILL: i64 = 47;
+const TEXT_SUPPORT_CARD: i64 = 75;
+
+/// Kinds of ids that can be resolved to a name.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NameKind {
+    Chara,
+    Card,
+    SupportCard,
+    Skill,
+    RaceInstance,
+    Course,
+    Track,
+}
+
+impl NameKind {
+    /// The kind of id stored under a member, by its normalized name.
+    fn from_key(normalized: &str) -> Option<NameKind> {
+        Some(match normalized {
+            "charaid" => NameKind::Chara,
+            "cardid" => NameKind::Card,
+            "supportcardid" => NameKind::SupportCard,
+            "skillid" => NameKind::Skill,
+            "raceinstanceid" => NameKind::RaceInstance,
+            "racecoursesetid" | "coursesetid" | "courseid" => NameKind::Course,
+            "racetrackid" | "trackid" => NameKind::Track,
+            _ => return None,
+        })
+    }
+
+    /// Key of the name added next to an id, e.g. `horseACT_skillName`.
+    fn name_key(self) -> &'static str {
+        match self {
+            NameKind::Chara => "horseACT_charaName",
+            NameKind::Card => "horseACT_cardName",
+            NameKind::SupportCard => "horseACT_supportCardName",
+            NameKind::Skill => "horseACT_skillName",
+            NameKind::RaceInstance => "horseACT_raceName",
+            NameKind::Course => "horseACT_courseName",
+            NameKind::Track => "horseACT_trackName",
+        }
+    }
+}
+
+struct MasterDb {
+    // None once opening failed, so a missing database is only reported once.
+    conn: Option<Connection>,
+    cache: HashMap<(NameKind, i64), Option<String>>,
+}
+
+static MASTER: Mutex<Option<MasterDb>> = Mutex::new(None);
+
+fn open() -> MasterDb {
+    let path = master_db_path();
+    let conn = if !path.exists() {
+        log!(
+            "[Master] {} not found, ids will not be resolved to names.",
+            path.display()
+        );
+        None
+    } else {
+        match Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        ) {
+            Ok(conn) => {
+                log!("[Master] Resolving names from {}", path.display());
+                Some(conn)
+            }
+            Err(e) => {
+                log!("[Master] Failed to open {}: {}", path.display(), e);
+                None
+            }
+        }
+    };
+    MasterDb {
+        conn,
+        cache: HashMap::new(),
+    }
+}
+
+/// Looks up the name of an id in the game's master database. Results,
+/// including misses, are cached for the rest of the session.
+pub fn name(kind: NameKind, id: i64) -> Option<String> {
+    if !enrich_names() {
+        return None;
+    }
+    let mut guard = MASTER.lock().unwrap_or_else(|e| e.into_inner());
+    let master = guard.get_or_insert_with(open);
+    if let Some(cached) = master.cache.get(&(kind, id)) {
+        return cached.clone();
+    }
+
+    let conn = master.conn.as_ref()?;
+    let resolved = match query(conn, kind, id) {
+        Ok(name) => name,
+        Err(e) => {
+            log!("[Master] Lookup failed: {}", e);
+            None
+        }
+    };
+    master.cache.insert((kind, id), resolved.clone());
+    resolved
+}
+
+fn query(conn: &Connection, kind: NameKind, id: i64) -> rusqlite::Result<Option<String>> {
+    let text = |category: i64| {
+        conn.query_row(
+            "SELECT text FROM text_data WHERE category = ?1 AND \"index\" = ?2",
+            params![category, id],
+            |row| row.get(0),
+        )
+        .optional()
+    };
+
+    match kind {
+        NameKind::Chara => text(TEXT_CHARA),
+        NameKind::Card => text(TEXT_CARD),
+        NameKind::SupportCard => text(TEXT_SUPPORT_CARD),
+        NameKind::Skill => text(TEXT_SKILL),
+        NameKind::Track => text(TEXT_TRACK),
+        NameKind::RaceInstance => conn
+            .query_row(
+                "SELECT t.text FROM race_instance r
+                 JOIN text_data t ON t.category = ?1 AND t.\"index\" = r.race_id
+                 WHERE r.id = ?2",
+                params![TEXT_RACE, id],
+                |row| row.get(0),
+            )
+            .optional(),
+        // e.g. "Tokyo Turf 2400m"
+        NameKind::Course => conn
+            .query_row(
+                "SELECT t.text, c.ground, c.distance FROM race_course_set c
+                 JOIN text_data t ON t.category = ?1 AND t.\"index\" = c.race_track_id
+                 WHERE c.id = ?2",
+                params![TEXT_TRACK, id],
+                |row| {
+                    let track: String = row.get(0)?;
+                    let ground: i64 = row.get(1)?;
+                    let distance: i64 = row.get(2)?;
+                    let surface = match ground {
+                        1 => "Turf",
+                        2 => "Dirt",
+                        _ => "",
+                    };
+                    Ok([track, surface.to_string(), format!("{}m", distance)]
+                        .into_iter()
+                        .filter(|s| !s.is_empty())
+                        .collect::<Vec<_>>()
+                        .join(" "))
+                },
+            )
+            .optional(),
+    }
+}
+
+/// Adds the resolved name next to every known id member of `value`, e.g.
+/// `horseACT_skillName` after `skill_id`. Ids that cannot be resolved are
+/// left alone, so this is a no-op without a master database.
+pub fn enrich(value: &mut Value) {
+    if !enrich_names() {
+        return;
+    }
+    match value {
+        Value::Object(map) => {
+            let mut enriched = Map::with_capacity(map.len());
+            for (key, mut v) in std::mem::take(map) {
+                enrich(&mut v);
+                let name = NameKind::from_key(&normalize_key(&key))
+                    .zip(v.as_i64())
+                    .and_then(|(kind, id)| Some((kind, name(kind, id)?)));
+                enriched.insert(key, v);
+                if let Some((kind, name)) = name {
+                    enriched.insert(kind.name_key().to_string(), Value::String(name));
+                }
+            }
+            *map = enriched;
+        }
+        Value::Array(arr) => arr.iter_mut().for_each(enrich),
+        _ => {}
+    }
+}
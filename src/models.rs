@@ -0,0 +1,646 @@
+use crate::lookup::{find_field, flatten_fields, normalize_key, value_to_plain_string};
+use serde::de::{self, DeserializeOwned};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+// Horse and veteran members sit on the object itself or one or two levels
+// down, e.g. on the response data a RaceHorse wraps.
+const MEMBER_SEARCH_DEPTH: usize = 2;
+const TEAM_TRIALS_SEARCH_DEPTH: usize = 3;
+
+/// Typed view of a dumped `RaceInfo`. Only the members horseACT relies on
+/// are read; everything else in the dump is ignored.
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RaceInfo {
+    pub race_type: Option<String>,
+    /// In the order of the dump, which is not necessarily gate order.
+    pub horses: Vec<RaceHorse>,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RaceHorse {
+    /// Position in the dump's `RaceHorse` array.
+    #[serde(skip)]
+    pub index: usize,
+    pub chara_id: Option<i64>,
+    pub chara_name: Option<String>,
+    /// 0-based.
+    pub finish_order: i64,
+    /// Seconds, as simulated.
+    pub finish_time_raw: f64,
+    /// Seconds, as displayed in game.
+    pub finish_time: Option<f64>,
+    /// 1-based gate number.
+    pub gate: Option<i64>,
+    pub running_style: Option<RunningStyle>,
+    pub popularity: Option<i64>,
+    pub stats: Stats,
+    /// Set when the capture marks the local player's horses.
+    pub is_mine: Option<bool>,
+    /// 1-based number of the horse's owner within the race.
+    pub owner_slot: Option<i64>,
+}
+
+#[derive(Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Stats {
+    pub speed: Option<i64>,
+    pub stamina: Option<i64>,
+    pub power: Option<i64>,
+    pub guts: Option<i64>,
+    pub wit: Option<i64>,
+}
+
+/// Typed view of a Team Trials result response.
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TeamTrialResult {
+    pub races: Vec<TeamTrialRace>,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TeamTrialRace {
+    pub round: Option<i64>,
+    pub race_instance_id: Option<i64>,
+    pub win_type: Option<i64>,
+    pub horse_count: usize,
+}
+
+/// Typed view of one `TrainedChara` of the veteran list.
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TrainedChara {
+    pub trained_chara_id: i64,
+    pub card_id: Option<i64>,
+    pub chara_id: Option<i64>,
+    pub rank_score: Option<i64>,
+    /// Whether the veteran is locked as a favourite.
+    pub is_favourite: Option<bool>,
+    pub running_style: Option<RunningStyle>,
+    pub stats: Stats,
+    pub aptitudes: Aptitudes,
+    pub skills: Vec<LearnedSkill>,
+}
+
+/// Aptitude grades as the game stores them, 1 (G) to 8 (S).
+#[derive(Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Aptitudes {
+    pub turf: Option<i64>,
+    pub dirt: Option<i64>,
+    pub short: Option<i64>,
+    pub mile: Option<i64>,
+    pub medium: Option<i64>,
+    pub long: Option<i64>,
+    pub front_runner: Option<i64>,
+    pub pace_chaser: Option<i64>,
+    pub late_surger: Option<i64>,
+    pub end_closer: Option<i64>,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LearnedSkill {
+    pub skill_id: i64,
+    pub level: Option<i64>,
+}
+
+/// The `<RaceType>` member of a dumped `RaceInfo`, e.g. `RoomMatch`.
+pub fn race_type(race_info: &Value) -> Option<&str> {
+    race_info
+        .get("<RaceType>k__BackingField")
+        .and_then(|v| v.as_str())
+}
+
+/// A horse's strategy, `RunningStyle` in the game. Dumps carry it either as
+/// the enum name (`Nige`) or as its number.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RunningStyle {
+    FrontRunner = 1,
+    PaceChaser = 2,
+    LateSurger = 3,
+    EndCloser = 4,
+}
+
+impl RunningStyle {
+    const ALL: [RunningStyle; 4] = [
+        RunningStyle::FrontRunner,
+        RunningStyle::PaceChaser,
+        RunningStyle::LateSurger,
+        RunningStyle::EndCloser,
+    ];
+
+    pub fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Number(n) => Self::ALL
+                .into_iter()
+                .find(|s| Some(*s as i64) == n.as_i64()),
+            Value::String(name) => Self::ALL
+                .into_iter()
+                .find(|s| normalize_key(s.game_name()) == normalize_key(name)),
+            _ => None,
+        }
+    }
+
+    /// The name the game shows, e.g. `Front Runner`.
+    pub fn name(self) -> &'static str {
+        match self {
+            RunningStyle::FrontRunner => "Front Runner",
+            RunningStyle::PaceChaser => "Pace Chaser",
+            RunningStyle::LateSurger => "Late Surger",
+            RunningStyle::EndCloser => "End Closer",
+        }
+    }
+
+    /// The enum name in the game's code, e.g. `Nige`.
+    pub fn game_name(self) -> &'static str {
+        match self {
+            RunningStyle::FrontRunner => "Nige",
+            RunningStyle::PaceChaser => "Senko",
+            RunningStyle::LateSurger => "Sashi",
+            RunningStyle::EndCloser => "Oikomi",
+        }
+    }
+}
+
+impl Serialize for RunningStyle {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.game_name())
+    }
+}
+
+impl<'de> Deserialize<'de> for RunningStyle {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        Self::from_value(&value)
+            .ok_or_else(|| de::Error::custom(format!("unknown running style {}", value)))
+    }
+}
+
+// The members below are read from `flatten_fields`, so their names are in
+// `normalize_key` form.
+
+#[derive(Deserialize)]
+struct HorseMembers {
+    #[serde(rename = "charaid", default, deserialize_with = "lenient")]
+    chara_id: Option<i64>,
+    #[serde(rename = "charaname", default, deserialize_with = "lenient_text")]
+    chara_name: Option<String>,
+    #[serde(rename = "finishorder", default, deserialize_with = "lenient")]
+    finish_order: Option<i64>,
+    #[serde(rename = "finishtimeraw", default, deserialize_with = "lenient")]
+    finish_time_raw: Option<f64>,
+    #[serde(rename = "finishtime", default, deserialize_with = "lenient")]
+    finish_time: Option<f64>,
+    #[serde(rename = "frameorder", default, deserialize_with = "lenient")]
+    frame_order: Option<i64>,
+    #[serde(rename = "runningstyle", default, deserialize_with = "lenient")]
+    running_style: Option<RunningStyle>,
+    #[serde(rename = "popularity", default, deserialize_with = "lenient")]
+    popularity: Option<i64>,
+    #[serde(rename = "horseactismine", default, deserialize_with = "lenient")]
+    is_mine: Option<bool>,
+    #[serde(rename = "horseactownerslot", default, deserialize_with = "lenient")]
+    owner_slot: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct StatMembers {
+    #[serde(default, deserialize_with = "lenient")]
+    speed: Option<i64>,
+    #[serde(default, deserialize_with = "lenient")]
+    stamina: Option<i64>,
+    #[serde(default, deserialize_with = "lenient")]
+    power: Option<i64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pow: Option<i64>,
+    #[serde(default, deserialize_with = "lenient")]
+    guts: Option<i64>,
+    #[serde(default, deserialize_with = "lenient")]
+    wiz: Option<i64>,
+    #[serde(default, deserialize_with = "lenient")]
+    wisdom: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct AptitudeMembers {
+    #[serde(rename = "propergroundturf", default, deserialize_with = "lenient")]
+    turf: Option<i64>,
+    #[serde(rename = "propergrounddirt", default, deserialize_with = "lenient")]
+    dirt: Option<i64>,
+    #[serde(rename = "properdistanceshort", default, deserialize_with = "lenient")]
+    short: Option<i64>,
+    #[serde(rename = "properdistancemile", default, deserialize_with = "lenient")]
+    mile: Option<i64>,
+    #[serde(rename = "properdistancemiddle", default, deserialize_with = "lenient")]
+    medium: Option<i64>,
+    #[serde(rename = "properdistancelong", default, deserialize_with = "lenient")]
+    long: Option<i64>,
+    #[serde(
+        rename = "properrunningstylenige",
+        default,
+        deserialize_with = "lenient"
+    )]
+    front_runner: Option<i64>,
+    #[serde(
+        rename = "properrunningstylesenko",
+        default,
+        deserialize_with = "lenient"
+    )]
+    pace_chaser: Option<i64>,
+    #[serde(
+        rename = "properrunningstylesashi",
+        default,
+        deserialize_with = "lenient"
+    )]
+    late_surger: Option<i64>,
+    #[serde(
+        rename = "properrunningstyleoikomi",
+        default,
+        deserialize_with = "lenient"
+    )]
+    end_closer: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct CharaMembers {
+    #[serde(rename = "trainedcharaid", default, deserialize_with = "lenient")]
+    trained_chara_id: Option<i64>,
+    #[serde(rename = "cardid", default, deserialize_with = "lenient")]
+    card_id: Option<i64>,
+    #[serde(rename = "charaid", default, deserialize_with = "lenient")]
+    chara_id: Option<i64>,
+    #[serde(rename = "rankscore", default, deserialize_with = "lenient")]
+    rank_score: Option<i64>,
+    #[serde(rename = "islocked", default, deserialize_with = "lenient")]
+    is_locked: Option<Flag>,
+    #[serde(rename = "favoriteflag", default, deserialize_with = "lenient")]
+    favorite_flag: Option<Flag>,
+    #[serde(rename = "runningstyle", default, deserialize_with = "lenient")]
+    running_style: Option<RunningStyle>,
+    #[serde(rename = "skillarray", default, deserialize_with = "lenient")]
+    skills: Option<Vec<Value>>,
+}
+
+#[derive(Deserialize)]
+struct SkillMembers {
+    #[serde(rename = "skillid")]
+    skill_id: i64,
+    #[serde(default, deserialize_with = "lenient")]
+    level: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct TeamTrialRaceMembers {
+    #[serde(default, deserialize_with = "lenient")]
+    round: Option<i64>,
+    #[serde(rename = "raceinstanceid", default, deserialize_with = "lenient")]
+    race_instance_id: Option<i64>,
+    #[serde(rename = "wintype", default, deserialize_with = "lenient")]
+    win_type: Option<i64>,
+    #[serde(rename = "racehorsedataarray", default, deserialize_with = "lenient")]
+    horses: Option<Vec<Value>>,
+}
+
+/// A flag dumped either as a bool or as 0/1.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Flag {
+    Bool(bool),
+    Int(i64),
+}
+
+impl Flag {
+    fn is_set(&self) -> bool {
+        match self {
+            Flag::Bool(b) => *b,
+            Flag::Int(i) => *i != 0,
+        }
+    }
+}
+
+// Optional members of an unexpected type read as missing rather than failing
+// the whole object.
+fn lenient<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    Ok(serde_json::from_value(Value::deserialize(deserializer)?).ok())
+}
+
+fn lenient_text<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(Some(value_to_plain_string(&Value::deserialize(
+        deserializer,
+    )?)))
+}
+
+fn flatten(value: &Value) -> Value {
+    flatten_fields(value, MEMBER_SEARCH_DEPTH)
+}
+
+fn read<T: DeserializeOwned>(fields: &Value) -> Result<T, String> {
+    T::deserialize(fields).map_err(|e| e.to_string())
+}
+
+impl Stats {
+    fn from_fields(fields: &Value) -> Result<Self, String> {
+        let m: StatMembers = read(fields)?;
+        Ok(Stats {
+            speed: m.speed,
+            stamina: m.stamina,
+            power: m.power.or(m.pow),
+            guts: m.guts,
+            wit: m.wiz.or(m.wisdom),
+        })
+    }
+
+    /// Speed, stamina, power, guts and wit, in that order.
+    pub fn as_array(&self) -> [Option<i64>; 5] {
+        [self.speed, self.stamina, self.power, self.guts, self.wit]
+    }
+}
+
+impl Aptitudes {
+    fn from_fields(fields: &Value) -> Result<Self, String> {
+        let m: AptitudeMembers = read(fields)?;
+        Ok(Aptitudes {
+            turf: m.turf,
+            dirt: m.dirt,
+            short: m.short,
+            mile: m.mile,
+            medium: m.medium,
+            long: m.long,
+            front_runner: m.front_runner,
+            pace_chaser: m.pace_chaser,
+            late_surger: m.late_surger,
+            end_closer: m.end_closer,
+        })
+    }
+}
+
+impl RaceInfo {
+    /// Reads the race from a dump. Fails when a member every race has is
+    /// missing or of the wrong type, which usually means a game update
+    /// renamed it.
+    pub fn from_value(race_info: &Value) -> Result<Self, String> {
+        let horses = horse_array(race_info)?
+            .iter()
+            .enumerate()
+            .map(|(i, h)| {
+                RaceHorse::from_value(i, h).map_err(|e| format!("RaceHorse[{}]: {}", i, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(RaceInfo {
+            race_type: race_type(race_info).map(|s| s.to_string()),
+            horses,
+        })
+    }
+
+    /// Reads the race like `from_value`, but keeps every horse that can be
+    /// read. Returns why the others were left out; a horse's `index` still
+    /// points at its place in the dump.
+    pub fn from_value_lenient(race_info: &Value) -> (Self, Vec<String>) {
+        let mut horses = Vec::new();
+        let mut errors = Vec::new();
+        match horse_array(race_info) {
+            Ok(array) => {
+                for (i, h) in array.iter().enumerate() {
+                    match RaceHorse::from_value(i, h) {
+                        Ok(horse) => horses.push(horse),
+                        Err(e) => errors.push(format!("RaceHorse[{}]: {}", i, e)),
+                    }
+                }
+            }
+            Err(e) => errors.push(e.to_string()),
+        }
+
+        let race = RaceInfo {
+            race_type: race_type(race_info).map(|s| s.to_string()),
+            horses,
+        };
+        (race, errors)
+    }
+
+    /// Checks that the horses form a consistent result: at least one horse,
+    /// every place taken once and plausible finish times.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.horses.is_empty() {
+            return Err("RaceHorse is empty".to_string());
+        }
+        let count = self.horses.len() as i64;
+        let mut taken = vec![false; self.horses.len()];
+        for (i, horse) in self.horses.iter().enumerate() {
+            if !(0..count).contains(&horse.finish_order) {
+                return Err(format!(
+                    "RaceHorse[{}]: FinishOrder {} is out of range for {} horses",
+                    i, horse.finish_order, count
+                ));
+            }
+            if std::mem::replace(&mut taken[horse.finish_order as usize], true) {
+                return Err(format!(
+                    "RaceHorse[{}]: FinishOrder {} is taken twice",
+                    i, horse.finish_order
+                ));
+            }
+            if !horse.finish_time_raw.is_finite() || horse.finish_time_raw < 0.0 {
+                return Err(format!(
+                    "RaceHorse[{}]: FinishTimeRaw {} is not a valid time",
+                    i, horse.finish_time_raw
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn winner(&self) -> Option<&RaceHorse> {
+        self.horses.iter().find(|h| h.finish_order == 0)
+    }
+
+    /// Horses in simulation order, which is gate order: slot `i` holds the
+    /// horse in gate `i + 1`. Horses without a gate take the slot of their
+    /// place in the dump; slots of horses that could not be read are `None`.
+    pub fn horses_by_slot(&self) -> Vec<Option<&RaceHorse>> {
+        let mut slots = Vec::new();
+        for horse in &self.horses {
+            let slot = horse
+                .gate
+                .and_then(|gate| usize::try_from(gate - 1).ok())
+                .unwrap_or(horse.index);
+            if slots.len() <= slot {
+                slots.resize(slot + 1, None);
+            }
+            slots[slot] = Some(horse);
+        }
+        slots
+    }
+}
+
+fn horse_array(race_info: &Value) -> Result<&Vec<Value>, &'static str> {
+    race_info
+        .get("<RaceHorse>k__BackingField")
+        .ok_or("RaceHorse is missing")?
+        .as_array()
+        .ok_or("RaceHorse is not an array")
+}
+
+impl RaceHorse {
+    /// Reads the horse at `index` of the dump's `RaceHorse` array.
+    pub fn from_value(index: usize, horse: &Value) -> Result<Self, String> {
+        let fields = flatten(horse);
+        let m: HorseMembers = read(&fields)?;
+        Ok(RaceHorse {
+            index,
+            chara_id: m.chara_id,
+            chara_name: m.chara_name,
+            finish_order: m.finish_order.ok_or("FinishOrder is missing")?,
+            finish_time_raw: m.finish_time_raw.ok_or("FinishTimeRaw is missing")?,
+            finish_time: m.finish_time,
+            gate: m.frame_order,
+            running_style: m.running_style,
+            popularity: m.popularity,
+            stats: Stats::from_fields(&fields)?,
+            is_mine: m.is_mine,
+            owner_slot: m.owner_slot,
+        })
+    }
+}
+
+impl TeamTrialResult {
+    /// Reads the races of a result response; each race is an entry of the
+    /// response's race result array.
+    pub fn from_value(response: &Value) -> Result<Self, String> {
+        let races = find_field(response, &["race_result_array"], TEAM_TRIALS_SEARCH_DEPTH)
+            .ok_or("race_result_array is missing")?
+            .as_array()
+            .ok_or("race_result_array is not an array")?
+            .iter()
+            .map(|race| {
+                let m: TeamTrialRaceMembers = read(&flatten(race))?;
+                Ok(TeamTrialRace {
+                    round: m.round,
+                    race_instance_id: m.race_instance_id,
+                    win_type: m.win_type,
+                    horse_count: m.horses.map_or(0, |horses| horses.len()),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(TeamTrialResult { races })
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.races.is_empty() {
+            return Err("race_result_array is empty".to_string());
+        }
+        match self.races.iter().position(|r| r.horse_count == 0) {
+            Some(i) => Err(format!("race_result_array[{}]: no horses", i)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl TrainedChara {
+    pub fn from_value(chara: &Value) -> Result<Self, String> {
+        let fields = flatten(chara);
+        let m: CharaMembers = read(&fields)?;
+        Ok(TrainedChara {
+            trained_chara_id: m.trained_chara_id.ok_or("trainedCharaId is missing")?,
+            card_id: m.card_id,
+            chara_id: m.chara_id,
+            rank_score: m.rank_score,
+            is_favourite: m.is_locked.or(m.favorite_flag).map(|flag| flag.is_set()),
+            running_style: m.running_style,
+            stats: Stats::from_fields(&fields)?,
+            aptitudes: Aptitudes::from_fields(&fields)?,
+            skills: m
+                .skills
+                .into_iter()
+                .flatten()
+                .filter_map(|skill| read::<SkillMembers>(&flatten(&skill)).ok())
+                .map(|skill| LearnedSkill {
+                    skill_id: skill.skill_id,
+                    level: skill.level,
+                })
+                .collect(),
+        })
+    }
+
+    /// Reads the whole veteran list, failing on the first unreadable entry.
+    pub fn list_from_value(list: &Value) -> Result<Vec<Self>, String> {
+        list.as_array()
+            .ok_or("veteran list is not an array")?
+            .iter()
+            .enumerate()
+            .map(|(i, c)| Self::from_value(c).map_err(|e| format!("TrainedChara[{}]: {}", i, e)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn horse(finish_order: Value, running_style: Value) -> Value {
+        json!({
+            "<FinishOrder>k__BackingField": finish_order,
+            "<FinishTimeRaw>k__BackingField": 92.5,
+            "<RunningStyle>k__BackingField": running_style,
+            "<FrameOrder>k__BackingField": 2,
+            "<ResponseHorseData>k__BackingField": { "chara_id": 1001, "speed": 1200, "wiz": 800 },
+        })
+    }
+
+    #[test]
+    fn running_style_reads_name_or_number() {
+        let by_name = RaceHorse::from_value(0, &horse(json!(0), json!("Nige"))).unwrap();
+        assert_eq!(by_name.running_style, Some(RunningStyle::FrontRunner));
+        let by_number = RaceHorse::from_value(0, &horse(json!(0), json!(3))).unwrap();
+        assert_eq!(by_number.running_style, Some(RunningStyle::LateSurger));
+        let unknown = RaceHorse::from_value(0, &horse(json!(0), json!("Unknown"))).unwrap();
+        assert_eq!(unknown.running_style, None);
+
+        assert_eq!(RunningStyle::PaceChaser.name(), "Pace Chaser");
+        assert_eq!(RunningStyle::EndCloser.game_name(), "Oikomi");
+    }
+
+    #[test]
+    fn reads_nested_members() {
+        let horse = RaceHorse::from_value(0, &horse(json!(1), json!("Senko"))).unwrap();
+        assert_eq!(horse.finish_order, 1);
+        assert_eq!(horse.gate, Some(2));
+        assert_eq!(horse.chara_id, Some(1001));
+        assert_eq!(horse.stats.speed, Some(1200));
+        assert_eq!(horse.stats.wit, Some(800));
+    }
+
+    #[test]
+    fn lenient_race_keeps_readable_horses() {
+        let race_info = json!({
+            "<RaceHorse>k__BackingField": [
+                horse(json!(0), json!(1)),
+                horse(json!("first"), json!(1)),
+                { "<FinishOrder>k__BackingField": 1, "<FinishTimeRaw>k__BackingField": 93.0 },
+            ],
+        });
+        assert!(RaceInfo::from_value(&race_info).is_err());
+
+        let (race, errors) = RaceInfo::from_value_lenient(&race_info);
+        assert_eq!(errors.len(), 1);
+        let indexes: Vec<usize> = race.horses.iter().map(|h| h.index).collect();
+        assert_eq!(indexes, [0, 2]);
+
+        // The first horse is in gate 2, the last one has no gate and keeps
+        // the slot of its place in the dump.
+        let slots: Vec<Option<usize>> = race
+            .horses_by_slot()
+            .into_iter()
+            .map(|h| h.map(|h| h.index))
+            .collect();
+        assert_eq!(slots, [None, Some(0), Some(2)]);
+    }
+}
@@ -0,0 +1,82 @@
+use crate::config::is_field_blacklisted;
+use crate::lookup::find_field;
+use serde_json::Value;
+
+/// Members of a dumped horse that hold its owner's viewer id.
+const OWNER_ID_FIELDS: [&str; 4] = [
+    "_ownerViewerId",
+    "_viewerId",
+    "owner_viewer_id",
+    "viewer_id",
+];
+
+/// Owner id members that the blacklist would drop. They are kept while
+/// dumping a race so its horses can be attributed, then removed by
+/// [`mark_owners`]; they are listed as `pseudonymizedFields` in the envelope.
+pub fn owner_id_fields() -> Vec<String> {
+    OWNER_ID_FIELDS
+        .iter()
+        .filter(|name| is_field_blacklisted(name, &[]))
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// Adds `horseACT_isMine` and `horseACT_ownerSlot` to every `RaceHorse`,
+/// then removes the owner id members kept by [`owner_id_fields`], so the
+/// real viewer ids are never written out.
+///
+/// Owners are numbered from 1 in the order they first appear; horses without
+/// an owner (NPCs) get no slot. `isMine` is `null` when the local viewer id
+/// or the owners are unknown.
+pub fn mark_owners(race_info: &mut Value, local_viewer_id: Option<i64>) {
+    if let Some(horses) = race_info
+        .get_mut("<RaceHorse>k__BackingField")
+        .and_then(|v| v.as_array_mut())
+    {
+        let owners: Vec<Option<i64>> = horses
+            .iter()
+            .map(|h| {
+                find_field(h, &OWNER_ID_FIELDS, 2)
+                    .and_then(|v| v.as_i64())
+                    .filter(|id| *id != 0)
+            })
+            .collect();
+        let local_viewer_id = local_viewer_id.filter(|_| owners.iter().any(|o| o.is_some()));
+
+        let mut slots: Vec<i64> = Vec::new();
+        for (horse, owner) in horses.iter_mut().zip(owners) {
+            let slot = owner.map(|id| match slots.iter().position(|s| *s == id) {
+                Some(i) => i + 1,
+                None => {
+                    slots.push(id);
+                    slots.len()
+                }
+            });
+            if let Value::Object(map) = horse {
+                map.insert(
+                    "horseACT_isMine".to_string(),
+                    local_viewer_id
+                        .map(|local| Value::Bool(owner == Some(local)))
+                        .unwrap_or(Value::Null),
+                );
+                map.insert(
+                    "horseACT_ownerSlot".to_string(),
+                    slot.map(Value::from).unwrap_or(Value::Null),
+                );
+            }
+        }
+    }
+
+    strip_owner_ids(race_info, &owner_id_fields());
+}
+
+fn strip_owner_ids(value: &mut Value, fields: &[String]) {
+    match value {
+        Value::Object(map) => {
+            map.retain(|k, _| !fields.contains(k));
+            map.values_mut().for_each(|v| strip_owner_ids(v, fields));
+        }
+        Value::Array(arr) => arr.iter_mut().for_each(|v| strip_owner_ids(v, fields)),
+        _ => {}
+    }
+}
@@ -0,0 +1,490 @@
+use crate::config::{relative_to_save_root, save_root, sinks, SinkKind};
+use crate::course::CourseInfo;
+use crate::dedup::race_content_hash;
+use crate::formats::read_captures;
+use crate::index::capture_files;
+use crate::log;
+use crate::lookup::value_to_plain_string;
+use crate::models::{RaceInfo, RunningStyle};
+use crate::persistence::write_atomic;
+use crate::template::sanitize_component;
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+static PARQUET_LOCK: Mutex<()> = Mutex::new(());
+static WORKER: Mutex<Option<Sender<Job>>> = Mutex::new(None);
+
+const FLUSH_ROWS: usize = 20;
+const FLUSH_IDLE: Duration = Duration::from_secs(30);
+
+struct Job {
+    dir: PathBuf,
+    output: Value,
+    source: Option<PathBuf>,
+    tag: String,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Text,
+    Int,
+    Float,
+}
+
+// The exported schema. Columns are only ever added at the end so that
+// readers can rely on names and types staying put.
+const RACE_COLUMNS: &[(&str, Kind)] = &[
+    ("content_hash", Kind::Text),
+    ("captured_at_utc", Kind::Text),
+    ("captured_at_local", Kind::Text),
+    ("plugin_version", Kind::Text),
+    ("race_type", Kind::Text),
+    ("race_instance_id", Kind::Int),
+    ("track_id", Kind::Int),
+    ("course_id", Kind::Int),
+    ("distance", Kind::Int),
+    ("surface", Kind::Text),
+    ("ground_condition", Kind::Text),
+    ("weather", Kind::Text),
+    ("season", Kind::Text),
+    ("winner", Kind::Text),
+    ("finish_time", Kind::Float),
+    ("horse_count", Kind::Int),
+    ("source_path", Kind::Text),
+];
+
+const HORSE_COLUMNS: &[(&str, Kind)] = &[
+    ("content_hash", Kind::Text),
+    ("captured_at_utc", Kind::Text),
+    ("race_type", Kind::Text),
+    ("horse_index", Kind::Int),
+    ("chara_id", Kind::Int),
+    ("chara_name", Kind::Text),
+    ("finish_order", Kind::Int),
+    ("finish_time_raw", Kind::Float),
+    ("finish_time", Kind::Float),
+    ("frame_order", Kind::Int),
+    ("running_style", Kind::Text),
+    ("popularity", Kind::Int),
+];
+
+/// Adds a freshly captured race to its monthly Parquet files under `dir`.
+///
+/// Parquet files cannot be appended to, so each partition keeps its rows in
+/// a hidden `.staging-<month>.jsonl` file and both tables are rewritten from
+/// it. Races are handed to a single worker that stages them right away and
+/// rewrites a partition once `FLUSH_ROWS` races are waiting for it or no race
+/// has arrived for `FLUSH_IDLE`.
+pub fn append_race(dir: &Path, output: &Value, source: Option<&Path>, tag: &str) {
+    let job = Job {
+        dir: dir.to_path_buf(),
+        output: output.clone(),
+        source: source.map(Path::to_path_buf),
+        tag: tag.to_string(),
+    };
+    let mut worker = WORKER.lock().unwrap_or_else(|e| e.into_inner());
+    let jobs = worker.get_or_insert_with(|| {
+        let (jobs, queue) = mpsc::channel();
+        std::thread::spawn(move || run_worker(queue));
+        jobs
+    });
+    if jobs.send(job).is_err() {
+        log!("[{}] Parquet export skipped: the worker has stopped", tag);
+    }
+}
+
+fn run_worker(queue: Receiver<Job>) {
+    // Races staged but not yet in the Parquet files, by partition and month.
+    let mut pending: BTreeMap<(PathBuf, String), usize> = BTreeMap::new();
+    loop {
+        match queue.recv_timeout(FLUSH_IDLE) {
+            Ok(job) => {
+                if let Some(key) = stage(&job) {
+                    let count = pending.entry(key.clone()).or_default();
+                    *count += 1;
+                    if *count >= FLUSH_ROWS {
+                        pending.remove(&key);
+                        flush(&key.0, &key.1);
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                for (partition, month) in std::mem::take(&mut pending).into_keys() {
+                    flush(&partition, &month);
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+// Appends the race to its staging file and returns the partition and month
+// it went to.
+fn stage(job: &Job) -> Option<(PathBuf, String)> {
+    let source = job.source.as_deref().map(relative_to_save_root);
+    let Some((race_type, month, entry)) = race_entry(&job.output, source, None) else {
+        log!(
+            "[{}] Parquet export skipped: capture is not a race",
+            job.tag
+        );
+        return None;
+    };
+
+    let _guard = PARQUET_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let partition = job.dir.join(sanitize_component(&race_type));
+    let result = fs::create_dir_all(&partition)
+        .map_err(|e| e.to_string())
+        .and_then(|()| append_staging(&partition, &month, &entry));
+    match result {
+        Ok(()) => Some((partition, month)),
+        Err(e) => {
+            log!("[{}] Failed to stage race for Parquet: {}", job.tag, e);
+            None
+        }
+    }
+}
+
+fn flush(partition: &Path, month: &str) {
+    let _guard = PARQUET_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let result = read_staging(partition, month)
+        .and_then(|entries| write_partition(partition, month, &entries));
+    match result {
+        Ok(()) => {
+            log!(
+                "[Parquet] Updated Parquet export in {}",
+                partition.display()
+            );
+        }
+        Err(e) => {
+            log!("[Parquet] Failed to update Parquet export: {}", e);
+        }
+    }
+}
+
+/// Directory used by the one-shot conversion: the first `parquet` sink, or
+/// `Saved races/Parquet` when none is configured.
+pub fn bulk_dir() -> PathBuf {
+    sinks()
+        .iter()
+        .find(|s| s.kind == SinkKind::Parquet)
+        .map(|s| PathBuf::from(&s.path))
+        .unwrap_or_else(|| save_root().join("Parquet"))
+}
+
+/// Converts every race in the save root, whatever its file format, into the
+/// Parquet layout. Partitions found in the archive are replaced; races seen
+/// more than once are exported once.
+pub fn convert_all(dir: &Path) {
+    log!("[Parquet] Converting saved races into {}", dir.display());
+    let _guard = PARQUET_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let mut partitions: BTreeMap<(String, String), Vec<Value>> = BTreeMap::new();
+    let mut seen = HashSet::new();
+    for path in capture_files() {
+        if path.starts_with(dir) {
+            continue;
+        }
+        let Ok(captures) = read_captures(&path) else {
+            continue;
+        };
+        let modified = fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .ok()
+            .map(chrono::DateTime::<chrono::Local>::from);
+        for (_, output) in captures {
+            let source = Some(relative_to_save_root(&path));
+            let Some((race_type, month, entry)) = race_entry(&output, source, modified) else {
+                continue;
+            };
+            let hash = entry["race"]["content_hash"].as_str().unwrap_or("");
+            if !seen.insert(hash.to_string()) {
+                continue;
+            }
+            partitions
+                .entry((race_type, month))
+                .or_default()
+                .push(entry);
+        }
+    }
+
+    let mut races = 0;
+    for ((race_type, month), entries) in &partitions {
+        let partition = dir.join(sanitize_component(race_type));
+        let result = fs::create_dir_all(&partition)
+            .map_err(|e| e.to_string())
+            .and_then(|()| write_staging(&partition, month, entries))
+            .and_then(|()| write_partition(&partition, month, entries));
+        match result {
+            Ok(()) => races += entries.len(),
+            Err(e) => {
+                log!("[Parquet] Failed to write {} {}: {}", race_type, month, e);
+            }
+        }
+    }
+    log!(
+        "[Parquet] Converted {} race(s) into {} partition(s).",
+        races,
+        partitions.len()
+    );
+}
+
+/// Flattens one capture into `{"race": {...}, "horses": [...]}` along with
+/// the race type and `YYYY-MM` month it is filed under. Files saved before
+/// the envelope existed fall back to `modified` for their month.
+fn race_entry(
+    output: &Value,
+    source: Option<String>,
+    modified: Option<chrono::DateTime<chrono::Local>>,
+) -> Option<(String, String, Value)> {
+    let enveloped = output.get("schemaVersion").is_some() && output.get("data").is_some();
+    let data = if enveloped { &output["data"] } else { output };
+    if enveloped && output.get("captureType").and_then(|v| v.as_str()) != Some("race") {
+        return None;
+    }
+    // Horses that cannot be read are left out; a race without any is skipped.
+    let (race, errors) = RaceInfo::from_value_lenient(data);
+    for e in &errors {
+        log!(
+            "[Parquet] Leaving out a horse of {}: {}",
+            source.as_deref().unwrap_or("race"),
+            e
+        );
+    }
+    if race.horses.is_empty() {
+        log!(
+            "[Parquet] Skipping {}: no readable horses",
+            source.as_deref().unwrap_or("race")
+        );
+        return None;
+    }
+    let horse_count = race.horses.len() + errors.len();
+    let horses = race.horses;
+
+    let course = output
+        .get("horseACT_race")
+        .cloned()
+        .unwrap_or_else(|| CourseInfo::from_race_info(data, 0).to_value());
+    let text = |v: Option<&Value>| match v {
+        None | Some(Value::Null) => Value::Null,
+        Some(v) => Value::String(value_to_plain_string(v)),
+    };
+
+    let captured_at_utc = output
+        .get("capturedAt")
+        .and_then(|v| v.get("utc"))
+        .cloned()
+        .unwrap_or(Value::Null);
+    let captured_at_local = output
+        .get("capturedAt")
+        .and_then(|v| v.get("local"))
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string())
+        .or_else(|| modified.map(|t| t.to_rfc3339()));
+    let month = captured_at_local
+        .as_deref()
+        .and_then(|s| s.get(..7))
+        .unwrap_or("unknown")
+        .to_string();
+
+    let race_type = text(course.get("raceType"));
+    let content_hash = output
+        .get("contentHash")
+        .cloned()
+        .unwrap_or_else(|| Value::String(race_content_hash(data)));
+    let (winner, finish_time) = horses
+        .iter()
+        .find(|h| h.finish_order == 0)
+        .map(|h| (h.chara_name.clone(), h.finish_time_raw))
+        .unzip();
+
+    let race = json!({
+        "content_hash": content_hash,
+        "captured_at_utc": captured_at_utc,
+        "captured_at_local": captured_at_local,
+        "plugin_version": output.get("pluginVersion").or_else(|| data.get("horseACT_version")),
+        "race_type": race_type,
+        "race_instance_id": course.get("raceInstanceId"),
+        "track_id": course.get("trackId"),
+        "course_id": course.get("courseId"),
+        "distance": course.get("distance"),
+        "surface": text(course.get("surface")),
+        "ground_condition": text(course.get("groundCondition")),
+        "weather": text(course.get("weather")),
+        "season": text(course.get("season")),
+        "winner": winner,
+        "finish_time": finish_time,
+        "horse_count": horse_count,
+        "source_path": source,
+    });
+
+    let horse_rows: Vec<Value> = horses
+        .iter()
+        .map(|h| {
+            json!({
+                "content_hash": race["content_hash"],
+                "captured_at_utc": race["captured_at_utc"],
+                "race_type": race["race_type"],
+                "horse_index": h.index,
+                "chara_id": h.chara_id,
+                "chara_name": h.chara_name,
+                "finish_order": h.finish_order,
+                "finish_time_raw": h.finish_time_raw,
+                "finish_time": h.finish_time,
+                "frame_order": h.gate,
+                "running_style": h.running_style.map(RunningStyle::game_name),
+                "popularity": h.popularity,
+            })
+        })
+        .collect();
+
+    let race_type = race["race_type"].as_str().unwrap_or("Unknown").to_string();
+    Some((
+        race_type,
+        month,
+        json!({ "race": race, "horses": horse_rows }),
+    ))
+}
+
+fn staging_path(partition: &Path, month: &str) -> PathBuf {
+    partition.join(format!(".staging-{}.jsonl", month))
+}
+
+fn append_staging(partition: &Path, month: &str, entry: &Value) -> Result<(), String> {
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(staging_path(partition, month))
+        .map_err(|e| e.to_string())?;
+    writeln!(f, "{}", entry).map_err(|e| e.to_string())
+}
+
+fn write_staging(partition: &Path, month: &str, entries: &[Value]) -> Result<(), String> {
+    let mut out = String::new();
+    for entry in entries {
+        out.push_str(&entry.to_string());
+        out.push('\n');
+    }
+    write_atomic(&staging_path(partition, month), out.as_bytes()).map_err(|e| e.to_string())
+}
+
+// A line cut short by a crash simply fails to parse and is dropped.
+fn read_staging(partition: &Path, month: &str) -> Result<Vec<Value>, String> {
+    let contents = fs::read_to_string(staging_path(partition, month)).map_err(|e| e.to_string())?;
+    Ok(contents
+        .lines()
+        .filter_map(|l| serde_json::from_str(l).ok())
+        .collect())
+}
+
+fn write_partition(partition: &Path, month: &str, entries: &[Value]) -> Result<(), String> {
+    let races: Vec<&Value> = entries.iter().map(|e| &e["race"]).collect();
+    let horses: Vec<&Value> = entries
+        .iter()
+        .filter_map(|e| e["horses"].as_array())
+        .flatten()
+        .collect();
+
+    write_table(
+        &partition.join(format!("races-{}.parquet", month)),
+        "race",
+        RACE_COLUMNS,
+        &races,
+    )?;
+    write_table(
+        &partition.join(format!("horses-{}.parquet", month)),
+        "horse",
+        HORSE_COLUMNS,
+        &horses,
+    )
+}
+
+fn write_table(
+    path: &Path,
+    name: &str,
+    columns: &[(&str, Kind)],
+    rows: &[&Value],
+) -> Result<(), String> {
+    let fields: Vec<String> = columns
+        .iter()
+        .map(|(column, kind)| match kind {
+            Kind::Text => format!("OPTIONAL BYTE_ARRAY {} (UTF8);", column),
+            Kind::Int => format!("OPTIONAL INT64 {};", column),
+            Kind::Float => format!("OPTIONAL DOUBLE {};", column),
+        })
+        .collect();
+    let message = format!("message {} {{ {} }}", name, fields.join(" "));
+    let schema = Arc::new(parse_message_type(&message).map_err(|e| e.to_string())?);
+    let props = Arc::new(
+        WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build(),
+    );
+
+    let mut buf = Vec::new();
+    let mut writer =
+        SerializedFileWriter::new(&mut buf, schema, props).map_err(|e| e.to_string())?;
+    let mut row_group = writer.next_row_group().map_err(|e| e.to_string())?;
+    for (column, kind) in columns {
+        let mut col = row_group
+            .next_column()
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("schema is missing column {}", column))?;
+        let cells = rows.iter().map(|r| r.get(*column));
+        let written = match kind {
+            Kind::Text => {
+                let (values, levels) = definition_levels(cells, |v| {
+                    Some(ByteArray::from(value_to_plain_string(v).as_str()))
+                });
+                col.typed::<ByteArrayType>()
+                    .write_batch(&values, Some(&levels), None)
+            }
+            Kind::Int => {
+                let (values, levels) = definition_levels(cells, |v| v.as_i64());
+                col.typed::<Int64Type>()
+                    .write_batch(&values, Some(&levels), None)
+            }
+            Kind::Float => {
+                let (values, levels) = definition_levels(cells, |v| v.as_f64());
+                col.typed::<DoubleType>()
+                    .write_batch(&values, Some(&levels), None)
+            }
+        };
+        written.map_err(|e| format!("column {}: {}", column, e))?;
+        col.close().map_err(|e| e.to_string())?;
+    }
+    row_group.close().map_err(|e| e.to_string())?;
+    writer.close().map_err(|e| e.to_string())?;
+
+    write_atomic(path, &buf).map_err(|e| e.to_string())
+}
+
+// Splits optional cells into the non-null values and the definition level of
+// every row (1 = present, 0 = null), as the column writer expects.
+fn definition_levels<'a, T>(
+    cells: impl Iterator<Item = Option<&'a Value>>,
+    convert: impl Fn(&Value) -> Option<T>,
+) -> (Vec<T>, Vec<i16>) {
+    let mut values = Vec::new();
+    let mut levels = Vec::new();
+    for cell in cells {
+        match cell.filter(|v| !v.is_null()).and_then(&convert) {
+            Some(v) => {
+                values.push(v);
+                levels.push(1);
+            }
+            None => levels.push(0),
+        }
+    }
+    (values, levels)
+}
@@ -1,106 +1,159 @@
-use crate::config::{save_career_races, save_root, save_tt_races};
+use crate::api::{endpoint_configs, extract_fields};
+use crate::builds;
+use crate::capture_rules;
+use crate::config::{
+    api_response_file_template, force_save_duplicates, keep_team_trials_response, output_format,
+    race_file_template, race_type_folder, save_career_races, save_race_type, save_tt_races,
+    split_team_trials, team_trials_file_template, veterans_file_template, OutputFormat,
+};
+use crate::course::CourseInfo;
+use crate::dedup::{self, race_content_hash};
+use crate::envelope::{wrap, CaptureType};
+use crate::formats::encode;
 use crate::log;
+use crate::master;
+use crate::models::{self, RaceInfo, TeamTrialResult, TrainedChara};
+use crate::schema;
+use crate::sinks::{self, Capture};
+use crate::summary::RaceSummary;
+use crate::team_trials::{self, RoundSummary};
+use crate::template::{render, strip_extension, unique_path, with_extension, TemplateContext};
+use crate::veteran_history;
 use serde_json::Value;
+use std::collections::HashSet;
 use std::fs::{self, File};
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
-pub fn save_race_info(mut race_info: Value) {
+// Race types already reported as missing from `raceTypeFolders` this session.
+static UNMAPPED_RACE_TYPES: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+/// Saves a dumped race. Returns where it was saved, as recorded in the
+/// duplicate index.
+pub fn save_race_info(mut race_info: Value, race_track_id: i32) -> Option<String> {
     if let Some(sim_data) = race_info.get("<SimDataBase64>k__BackingField") {
         if sim_data.is_null() {
             log!("[RaceInfo] Skipped saving: <SimDataBase64>k__BackingField is null.");
-            return;
+            return None;
         }
     }
 
-    if let Value::Object(ref mut map) = race_info {
-        map.insert(
-            "horseACT_version".to_string(),
-            Value::String(env!("CARGO_PKG_VERSION").to_string()),
+    let race_type = models::race_type(&race_info).map(|t| t.to_string());
+    let folder = folder_of(race_type.as_deref());
+
+    if race_type.as_deref() == Some("Single") && !save_career_races() {
+        log!("[RaceInfo] Skipped saving Career race because saveCareerRaces is disabled.");
+        return None;
+    }
+    if !save_race_type(race_type.as_deref()) {
+        log!(
+            "[RaceInfo] Skipped saving {} race because it is disabled in saveRaceTypes.",
+            race_type.as_deref().unwrap_or("Unknown")
         );
+        return None;
     }
 
-    let now = chrono::Local::now();
-    let date_str = now.format("%Y%m%d").to_string();
-
-    let mut filename = format!("{}.json", now.format("%Y%m%d_%H%M%S_%3f"));
-
-    if let Some(horses) = race_info
-        .get("<RaceHorse>k__BackingField")
-        .and_then(|v| v.as_array())
-    {
-        let winner_opt = horses
-            .iter()
-            .find(|h| h.get("FinishOrder").and_then(|v| v.as_i64()) == Some(0));
-
-        if let Some(winner) = winner_opt {
-            let name = winner
-                .get("<charaName>k__BackingField")
-                .and_then(|v| v.as_str())
-                .unwrap_or("Unknown");
-
-            let raw_time = winner
-                .get("FinishTimeRaw")
-                .and_then(|v| v.as_f64())
-                .unwrap_or(0.0);
-
-            let safe_name: String = name
-                .chars()
-                .map(|c| {
-                    if c.is_alphanumeric() || c == ' ' || c == '-' {
-                        c
-                    } else {
-                        '_'
-                    }
-                })
-                .collect();
-
-            filename = format!("{}-{:.4}s-{}.json", safe_name.trim(), raw_time, date_str);
+    let content_hash = race_content_hash(&race_info);
+    if dedup::is_known(&content_hash) {
+        if force_save_duplicates() {
+            log!(
+                "[RaceInfo] Race {} was saved before; saving again because forceSaveDuplicates is enabled.",
+                content_hash
+            );
+        } else {
+            log!(
+                "[RaceInfo] Skipped saving: race {} was already saved.",
+                content_hash
+            );
+            dedup::record_resighting(&content_hash);
+            return None;
         }
     }
 
-    let folder = match race_info
-        .get("<RaceType>k__BackingField")
-        .and_then(|v| v.as_str())
-    {
-        Some("RoomMatch") => "Room match",
-        Some("Champions") => "Champions meeting",
-        Some("Single") => "Career",
-        Some("Practice") => "Practice room",
-        _ => "Other",
+    let race = match RaceInfo::from_value(&race_info) {
+        Ok(race) => {
+            if let Err(e) = race.validate() {
+                log!("[RaceInfo] Warning: race data failed validation: {}", e);
+            }
+            Some(race)
+        }
+        Err(e) => {
+            log!(
+                "[RaceInfo] Warning: could not read race data, a game update may have changed its layout: {}",
+                e
+            );
+            None
+        }
     };
 
-    if folder == "Career" && !save_career_races() {
-        log!("[RaceInfo] Skipped saving Career race because saveCareerRaces is disabled.");
-        return;
+    let course = CourseInfo::from_race_info(&race_info, race_track_id);
+    let verdict = capture_rules::evaluate(race_type.as_deref(), &course, race.as_ref());
+    if !verdict.save {
+        log!("[RaceInfo] Skipped saving: the race was not selected by captureRules.");
+        return None;
     }
+    let captured_at = chrono::Local::now();
 
-    let dir = save_root().join(folder);
-    if !dir.exists() {
-        if let Err(e) = fs::create_dir_all(&dir) {
-            log!("[RaceInfo] Failed to create dir {:?}: {}", dir, e);
-            return;
-        }
+    let mut ctx = TemplateContext::new(captured_at);
+    ctx.set("raceType", folder);
+    ctx.set("rawRaceType", race_type.as_deref().unwrap_or("Unknown"));
+    for (name, value) in course.placeholders() {
+        ctx.set(name, value);
     }
+    let (winner, finish_time) = race
+        .as_ref()
+        .and_then(winner_of)
+        .unwrap_or_else(|| ("Unknown".to_string(), 0.0));
+    ctx.set("winner", winner);
+    ctx.set("finishTime", format!("{:.4}", finish_time));
 
-    let path = dir.join(filename);
+    let summary = race
+        .as_ref()
+        .and_then(|r| serde_json::to_value(RaceSummary::from_race(r)).ok())
+        .unwrap_or(Value::Null);
 
-    match File::create(&path) {
-        Ok(mut f) => match serde_json::to_string_pretty(&race_info) {
-            Ok(json_str) => {
-                if let Err(e) = write!(f, "{}", json_str) {
-                    log!("[RaceInfo] Failed to write JSON: {}", e);
-                } else {
-                    log!("[RaceInfo] Saved to: {}", path.display());
-                }
-            }
-            Err(e) => {
-                log!("[RaceInfo] Failed to serialize JSON: {}", e);
-            }
-        },
-        Err(e) => {
-            log!("[RaceInfo] Failed to create file: {}", e);
-        }
+    // After hashing, so resolved names never change a race's identity.
+    master::enrich(&mut race_info);
+    let output = wrap(
+        CaptureType::Race,
+        captured_at,
+        vec![
+            ("contentHash", Value::String(content_hash.clone())),
+            ("summary", summary),
+            ("tags", Value::from(verdict.tags)),
+            ("horseACT_race", course.to_value()),
+        ],
+        race_info,
+    );
+
+    let capture = Capture {
+        capture_type: CaptureType::Race,
+        race_type: models::race_type(&output["data"]),
+        endpoint: None,
+        output: &output,
+        ctx: &ctx,
+        file_template: race_file_template(),
+        replace_existing: false,
+        tag: "RaceInfo",
+    };
+    let location = sinks::dispatch(&capture);
+    if let Some(location) = &location {
+        dedup::record_saved(&content_hash, location);
     }
+    schema::observe(CaptureType::Race, output);
+    location
+}
+
+/// Name and raw finish time of the horse with `FinishOrder == 0`.
+pub fn race_winner(race_info: &Value) -> Option<(String, f64)> {
+    winner_of(&RaceInfo::from_value(race_info).ok()?)
+}
+
+fn winner_of(race: &RaceInfo) -> Option<(String, f64)> {
+    let winner = race.winner()?;
+    let name = winner.chara_name.as_deref().unwrap_or("Unknown");
+    Some((name.to_string(), winner.finish_time_raw))
 }
 
 pub fn save_team_trial_result(mut response: Value) {
@@ -109,45 +162,63 @@ pub fn save_team_trial_result(mut response: Value) {
         return;
     }
 
-    if let Value::Object(ref mut map) = response {
-        map.insert(
-            "horseACT_version".to_string(),
-            Value::String(env!("CARGO_PKG_VERSION").to_string()),
-        );
-    }
-
-    let now = chrono::Local::now();
-    let filename = format!("TT-{}.json", now.format("%Y%m%d_%H%M%S_%3f"));
-    let dir = save_root().join("Team trials");
-
-    if !dir.exists() {
-        if let Err(e) = fs::create_dir_all(&dir) {
-            log!("[TeamTrials] Failed to create dir {:?}: {}", dir, e);
-            return;
-        }
+    let result = TeamTrialResult::from_value(&response);
+    if let Err(e) = result.clone().and_then(|r| r.validate()) {
+        log!("[TeamTrials] Warning: result data failed validation: {}", e);
     }
 
-    let path = dir.join(filename);
-    match File::create(&path) {
-        Ok(mut f) => match serde_json::to_string_pretty(&response) {
-            Ok(json_str) => {
-                if let Err(e) = write!(f, "{}", json_str) {
-                    log!("[TeamTrials] Failed to write JSON: {}", e);
-                } else {
-                    log!("[TeamTrials] Saved to: {}", path.display());
-                }
-            }
-            Err(e) => {
-                log!("[TeamTrials] Failed to serialize JSON: {}", e);
+    let mut metadata = Vec::new();
+    if split_team_trials() {
+        let races = team_trials::split_races(&response);
+        let saved: Vec<Option<String>> = races
+            .iter()
+            .map(|race_info| save_race_info(race_info.clone(), 0))
+            .collect();
+        let result_races = result.map(|r| r.races).unwrap_or_default();
+        // The response is only dropped when every race of it was read and
+        // saved on its own; otherwise it is the only copy of the round.
+        let all_saved = !races.is_empty()
+            && races.len() == result_races.len()
+            && races.iter().all(|r| RaceInfo::from_value(r).is_ok())
+            && saved.iter().all(Option::is_some);
+        let round = result_races
+            .into_iter()
+            .zip(races.iter())
+            .zip(saved)
+            .map(|((race, race_info), file)| (race, race_info, file))
+            .collect();
+        let summary = RoundSummary::new(&response, round);
+        metadata.push((
+            "summary",
+            serde_json::to_value(summary).unwrap_or(Value::Null),
+        ));
+        if !keep_team_trials_response() {
+            if all_saved {
+                response = Value::Null;
+            } else {
+                log!("[TeamTrials] Keeping the response: not every race could be read and saved.");
             }
-        },
-        Err(e) => {
-            log!("[TeamTrials] Failed to create file: {}", e);
         }
     }
+
+    let captured_at = chrono::Local::now();
+    let ctx = TemplateContext::new(captured_at);
+    master::enrich(&mut response);
+    let output = wrap(CaptureType::TeamTrials, captured_at, metadata, response);
+    sinks::dispatch(&Capture {
+        capture_type: CaptureType::TeamTrials,
+        race_type: None,
+        endpoint: None,
+        output: &output,
+        ctx: &ctx,
+        file_template: team_trials_file_template(),
+        replace_existing: false,
+        tag: "TeamTrials",
+    });
+    schema::observe(CaptureType::TeamTrials, output);
 }
 
-pub fn save_veteran_data(list_data: Value) {
+pub fn save_veteran_data(mut list_data: Value) {
     if !list_data.is_array() {
         log!(
             "[Veteran] Warning: Data is not an array, got: {:?}",
@@ -161,26 +232,200 @@ pub fn save_veteran_data(list_data: Value) {
             log!("[Veteran] No veteran characters to save (empty list)");
             return;
         }
-        log!("[Veteran] Saving {} veteran character(s)", arr.len());
+        log!("[Veteran] Received {} veteran character(s)", arr.len());
+    }
+
+    if let Err(e) = TrainedChara::list_from_value(&list_data) {
+        log!("[Veteran] Warning: veteran data failed validation: {}", e);
     }
 
-    let path = save_root().join("veterans.json");
+    builds::export(&list_data);
 
-    match File::create(&path) {
-        Ok(mut f) => match serde_json::to_string_pretty(&list_data) {
-            Ok(json_str) => {
-                if let Err(e) = write!(f, "{}", json_str) {
-                    log!("[Veteran] Failed to write JSON: {}", e);
-                } else {
-                    log!("[Veteran] Saved to: {}", path.display());
-                }
+    let captured_at = chrono::Local::now();
+    let ctx = TemplateContext::new(captured_at);
+
+    // The list is sent again every time the game refreshes it, usually
+    // unchanged. It is still saved when the "latest" file has gone missing.
+    let changes = veteran_history::diff(&list_data);
+    if changes.is_empty() && !sinks::replaced_file_missing(&veterans_capture(&Value::Null, &ctx)) {
+        log!("[Veteran] Skipped saving: the veteran list has not changed.");
+        return;
+    }
+
+    master::enrich(&mut list_data);
+    let output = wrap(CaptureType::Veterans, captured_at, Vec::new(), list_data);
+    if sinks::dispatch(&veterans_capture(&output, &ctx)).is_none() {
+        log!("[Veteran] Not recording veteran history: the list was not saved.");
+        return;
+    }
+    if !changes.is_empty() {
+        veteran_history::record(changes, &output, captured_at);
+    }
+    schema::observe(CaptureType::Veterans, output);
+}
+
+// The veterans file is the "latest" snapshot, so a template that resolves to
+// an existing name overwrites it instead of adding a suffix.
+fn veterans_capture<'a>(output: &'a Value, ctx: &'a TemplateContext) -> Capture<'a> {
+    Capture {
+        capture_type: CaptureType::Veterans,
+        race_type: None,
+        endpoint: None,
+        output,
+        ctx,
+        file_template: veterans_file_template(),
+        replace_existing: true,
+        tag: "Veteran",
+    }
+}
+
+/// Where the "latest" veterans snapshot lives under `root`, as the next
+/// save would resolve it.
+pub fn veterans_file(root: &Path) -> PathBuf {
+    let ctx = TemplateContext::new(chrono::Local::now());
+    let extension = output_format(CaptureType::Veterans).extension();
+    resolved_path(root, veterans_file_template(), &ctx, extension)
+}
+
+/// Saves the response captured by an endpoint hook, reduced to the fields
+/// its endpoint config asks for.
+pub fn save_api_response(endpoint: &str, response: &Value) {
+    let fields = endpoint_configs()
+        .iter()
+        .find(|e| e.name == endpoint)
+        .map(|e| e.fields.as_slice())
+        .unwrap_or(&[]);
+
+    let captured_at = chrono::Local::now();
+    let mut ctx = TemplateContext::new(captured_at);
+    ctx.set("endpoint", endpoint);
+    let output = wrap(
+        CaptureType::ApiResponse,
+        captured_at,
+        vec![("endpoint", Value::from(endpoint))],
+        extract_fields(response, fields),
+    );
+    sinks::dispatch(&Capture {
+        capture_type: CaptureType::ApiResponse,
+        race_type: None,
+        endpoint: Some(endpoint),
+        output: &output,
+        ctx: &ctx,
+        file_template: api_response_file_template(),
+        replace_existing: false,
+        tag: endpoint,
+    });
+}
+
+/// Folder of a race type from `raceTypeFolders`. Unmapped types go to
+/// `Other`; the first race of each such type in a session is logged so the
+/// mapping can be extended.
+fn folder_of(race_type: Option<&str>) -> &'static str {
+    let Some(race_type) = race_type else {
+        return "Other";
+    };
+    if let Some(folder) = race_type_folder(race_type) {
+        return folder;
+    }
+    let first_sighting = UNMAPPED_RACE_TYPES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_or_insert_with(HashSet::new)
+        .insert(race_type.to_string());
+    if first_sighting {
+        log!(
+            "[RaceInfo] Race type {} has no folder in raceTypeFolders, saving it under Other.",
+            race_type
+        );
+    }
+    "Other"
+}
+
+/// Resolves a file name template against `root` and makes sure the target
+/// directory exists. With `unique` set, an existing file is never
+/// overwritten; a numeric suffix is added instead.
+pub fn output_path(
+    root: &Path,
+    template: &str,
+    ctx: &TemplateContext,
+    extension: &str,
+    unique: bool,
+    tag: &str,
+) -> Option<PathBuf> {
+    let base = template_base(root, template, ctx, extension);
+
+    if let Some(dir) = base.parent() {
+        if !dir.exists() {
+            if let Err(e) = fs::create_dir_all(dir) {
+                log!("[{}] Failed to create dir {:?}: {}", tag, dir, e);
+                return None;
             }
-            Err(e) => {
-                log!("[Veteran] Failed to serialize JSON: {}", e);
+        }
+    }
+
+    Some(if unique {
+        unique_path(&base, extension)
+    } else {
+        with_extension(&base, extension)
+    })
+}
+
+/// The file a template resolves to under `root`, without the suffix
+/// `output_path` may add to keep it unique.
+pub fn resolved_path(
+    root: &Path,
+    template: &str,
+    ctx: &TemplateContext,
+    extension: &str,
+) -> PathBuf {
+    with_extension(&template_base(root, template, ctx, extension), extension)
+}
+
+fn template_base(root: &Path, template: &str, ctx: &TemplateContext, extension: &str) -> PathBuf {
+    root.join(strip_extension(render(template, ctx), extension))
+}
+
+pub fn write_capture(path: &Path, value: &Value, format: OutputFormat, tag: &str) -> bool {
+    match encode(format, value) {
+        Ok(bytes) => {
+            if let Err(e) = write_atomic(path, &bytes) {
+                log!("[{}] Failed to write file: {}", tag, e);
+                false
+            } else {
+                log!("[{}] Saved to: {}", tag, path.display());
+                true
             }
-        },
+        }
         Err(e) => {
-            log!("[Veteran] Failed to create file: {}", e);
+            log!("[{}] Failed to serialize capture: {}", tag, e);
+            false
         }
     }
 }
+
+// Write into a temp file next to the target, fsync it and rename it over the
+// target so readers never observe a truncated file.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp = temp_path_for(path);
+
+    let result = (|| {
+        let mut f = File::create(&tmp)?;
+        f.write_all(contents)?;
+        f.sync_all()?;
+        drop(f);
+        fs::rename(&tmp, path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+fn temp_path_for(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()))
+}
@@ -0,0 +1,425 @@
+use crate::config::OutputFormat;
+use crate::formats::{read_captures, sidecar_base};
+use crate::index::capture_files;
+use crate::log;
+use crate::lookup::value_to_plain_string;
+use crate::models::{RaceInfo, RunningStyle};
+use crate::persistence::write_atomic;
+use crate::simdata::{self, RaceSimulation};
+use crate::summary::format_race_time;
+use crate::timeline::{self, TimelineEvent, TimelineKind};
+use serde_json::Value;
+use std::fmt::Write;
+use std::path::Path;
+
+const CHART_WIDTH: f32 = 860.0;
+const CHART_HEIGHT: f32 = 300.0;
+const CHART_PAD_LEFT: f32 = 48.0;
+const CHART_PAD_RIGHT: f32 = 12.0;
+const CHART_PAD_TOP: f32 = 12.0;
+const CHART_PAD_BOTTOM: f32 = 28.0;
+// Frames beyond this are thinned out so reports stay small.
+const MAX_CHART_POINTS: usize = 400;
+
+const STYLE: &str = "body{font-family:system-ui,sans-serif;margin:24px;color:#222}\
+h1{font-size:1.4em;margin-bottom:4px}h2{font-size:1.1em;margin-top:28px}\
+.meta{color:#666;margin-top:0}table{border-collapse:collapse;font-size:.9em}\
+th,td{border:1px solid #ddd;padding:4px 8px;text-align:left}th{background:#f4f4f4}\
+td.num{text-align:right}.swatch{display:inline-block;width:10px;height:10px;margin-right:6px}\
+svg{background:#fafafa;border:1px solid #ddd}svg text{font-size:11px;fill:#666}";
+
+/// One `RaceHorse` entry, in gate order so that index `i` matches horse `i`
+/// of the simulation.
+struct Entry {
+    gate: i64,
+    name: String,
+    finish_order: i64,
+    finish_time: f64,
+    running_style: Option<RunningStyle>,
+    popularity: Option<i64>,
+    stats: [Option<i64>; 5],
+}
+
+const STAT_NAMES: [&str; 5] = ["Speed", "Stamina", "Power", "Guts", "Wit"];
+
+/// Renders a saved race as `<base>.html`. Meant to run off the game thread.
+pub fn write_report(base: &Path, output: &Value, tag: &str) {
+    let mut path = base.as_os_str().to_owned();
+    path.push(".html");
+    let path = Path::new(&path);
+
+    match write_atomic(path, render(output).as_bytes()) {
+        Ok(()) => {
+            log!("[{}] Saved report to: {}", tag, path.display());
+        }
+        Err(e) => {
+            log!("[{}] Failed to write report {}: {}", tag, path.display(), e);
+        }
+    }
+}
+
+/// Writes a report for every saved race that does not have one yet.
+pub fn generate_all() {
+    let mut written = 0;
+    for path in capture_files() {
+        let Some(format) = OutputFormat::from_path(&path) else {
+            continue;
+        };
+        let Ok(captures) = read_captures(&path) else {
+            continue;
+        };
+        for (line, output) in captures {
+            let data = output.get("data").unwrap_or(&output);
+            let is_race = match output.get("captureType").and_then(|v| v.as_str()) {
+                Some(capture_type) => capture_type == "race",
+                None => data.get("<RaceHorse>k__BackingField").is_some(),
+            };
+            let base = sidecar_base(&path, format, line);
+            let mut html = base.as_os_str().to_owned();
+            html.push(".html");
+            if is_race && !Path::new(&html).exists() {
+                write_report(&base, &output, "Report");
+                written += 1;
+            }
+        }
+    }
+    log!("[Report] Generated {} report(s).", written);
+}
+
+fn render(output: &Value) -> String {
+    let data = output.get("data").unwrap_or(output);
+    let course = output.get("horseACT_race").unwrap_or(&Value::Null);
+    let entries = entries(data);
+    let sim = data
+        .get("<SimDataBase64>k__BackingField")
+        .and_then(|v| v.as_str())
+        .and_then(|encoded| simdata::decode(encoded).ok());
+    let names: Vec<Option<String>> = entries
+        .iter()
+        .map(|e| e.as_ref().map(|e| e.name.clone()))
+        .collect();
+    let events = sim
+        .as_ref()
+        .map(|sim| timeline::build(sim, &names))
+        .unwrap_or_default();
+
+    let text = |key: &str| course.get(key).map(value_to_plain_string);
+    let title = [
+        text("raceType"),
+        text("distance").map(|d| format!("{}m", d)),
+        text("surface"),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>()
+    .join(" · ");
+    let title = if title.is_empty() {
+        "Race".to_string()
+    } else {
+        title
+    };
+
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{}</title><style>{}</style></head><body>",
+        escape(&title),
+        STYLE
+    );
+    let _ = write!(html, "<h1>{}</h1>", escape(&title));
+
+    let captured_at = output
+        .get("capturedAt")
+        .and_then(|v| v.get("local"))
+        .and_then(|v| v.as_str());
+    let meta: Vec<String> = [
+        captured_at.map(|t| t.to_string()),
+        text("courseId").map(|c| format!("Course {}", c)),
+        text("groundCondition").map(|g| format!("Ground: {}", g)),
+        text("weather").map(|w| format!("Weather: {}", w)),
+        text("season").map(|s| format!("Season: {}", s)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let _ = write!(html, "<p class=\"meta\">{}</p>", escape(&meta.join(" · ")));
+
+    results_table(&mut html, &entries, sim.as_ref(), &events);
+
+    if let Some(sim) = &sim {
+        html.push_str("<h2>Position</h2>");
+        html.push_str(&position_chart(sim));
+        html.push_str("<h2>Speed (m/s)</h2>");
+        html.push_str(&speed_chart(sim));
+        timeline_table(&mut html, &entries, &events);
+    } else {
+        html.push_str("<p class=\"meta\">No simulation data, charts are unavailable.</p>");
+    }
+
+    html.push_str("</body></html>\n");
+    html
+}
+
+// Horses that cannot be read leave their slot empty.
+fn entries(data: &Value) -> Vec<Option<Entry>> {
+    let (race, errors) = RaceInfo::from_value_lenient(data);
+    for e in errors {
+        log!("[Report] Could not read race data: {}", e);
+    }
+    race.horses_by_slot()
+        .into_iter()
+        .enumerate()
+        .map(|(slot, h)| {
+            let h = h?;
+            Some(Entry {
+                gate: slot as i64 + 1,
+                name: h
+                    .chara_name
+                    .clone()
+                    .unwrap_or_else(|| "Unknown".to_string()),
+                finish_order: h.finish_order,
+                finish_time: h.finish_time_raw,
+                running_style: h.running_style,
+                popularity: h.popularity,
+                stats: h.stats.as_array(),
+            })
+        })
+        .collect()
+}
+
+fn results_table(
+    html: &mut String,
+    entries: &[Option<Entry>],
+    sim: Option<&RaceSimulation>,
+    events: &[TimelineEvent],
+) {
+    html.push_str("<h2>Results</h2><table><tr><th>Place</th><th>Gate</th><th>Horse</th><th>Strategy</th><th>Time</th><th>Margin</th><th>Popularity</th>");
+    for stat in STAT_NAMES {
+        let _ = write!(html, "<th>{}</th>", stat);
+    }
+    html.push_str("<th>Skills</th></tr>");
+
+    let mut order: Vec<(usize, &Entry)> = entries
+        .iter()
+        .enumerate()
+        .filter_map(|(i, e)| Some((i, e.as_ref()?)))
+        .collect();
+    order.sort_by_key(|(_, e)| e.finish_order);
+
+    for (i, entry) in order {
+        let margin = sim
+            .and_then(|s| s.horses.get(i))
+            .filter(|r| r.finish_diff_time > 0.0)
+            .map(|r| format!("+{:.2}s", r.finish_diff_time))
+            .unwrap_or_default();
+        let skills: Vec<String> = events
+            .iter()
+            .filter(|e| e.horse == i)
+            .filter_map(|e| match e.kind {
+                TimelineKind::Skill {
+                    skill_id,
+                    ref skill_name,
+                } => Some(format!(
+                    "{} ({})",
+                    skill_label(skill_id, skill_name),
+                    format_race_time(e.time as f64)
+                )),
+                _ => None,
+            })
+            .collect();
+
+        let _ = write!(
+            html,
+            "<tr><td class=\"num\">{}</td><td class=\"num\">{}</td><td><span class=\"swatch\" style=\"background:{}\"></span>{}</td><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td>",
+            entry.finish_order + 1,
+            entry.gate,
+            color(i),
+            escape(&entry.name),
+            entry.running_style.map(RunningStyle::name).unwrap_or(""),
+            format_race_time(entry.finish_time),
+            margin,
+            entry.popularity.map(|p| p.to_string()).unwrap_or_default(),
+        );
+        for stat in entry.stats {
+            let _ = write!(
+                html,
+                "<td class=\"num\">{}</td>",
+                stat.map(|s| s.to_string()).unwrap_or_default()
+            );
+        }
+        let _ = write!(html, "<td>{}</td></tr>", escape(&skills.join(", ")));
+    }
+    html.push_str("</table>");
+}
+
+fn skill_label(skill_id: i32, skill_name: &Option<String>) -> String {
+    skill_name.clone().unwrap_or_else(|| skill_id.to_string())
+}
+
+fn timeline_table(html: &mut String, entries: &[Option<Entry>], events: &[TimelineEvent]) {
+    html.push_str("<h2>Timeline</h2><table><tr><th>Time</th><th>Horse</th><th>Event</th><th>Position</th><th>Distance</th></tr>");
+    for event in events {
+        let what = match event.kind {
+            TimelineKind::Skill {
+                skill_id,
+                ref skill_name,
+            } => format!("Skill {}", skill_label(skill_id, skill_name)),
+            TimelineKind::PositionChange { from } => format!(
+                "Moved from {} to {}",
+                from,
+                event.position.unwrap_or_default()
+            ),
+            TimelineKind::LastSpurt => "Last spurt".to_string(),
+            TimelineKind::StaminaExhausted => "Out of stamina".to_string(),
+            TimelineKind::Finish { place } => format!("Finished {}", ordinal(place)),
+        };
+        let name = entries
+            .get(event.horse)
+            .and_then(Option::as_ref)
+            .map(|e| e.name.as_str())
+            .unwrap_or("?");
+        let _ = write!(
+            html,
+            "<tr><td class=\"num\">{}</td><td><span class=\"swatch\" style=\"background:{}\"></span>{}</td><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td></tr>",
+            format_race_time(event.time as f64),
+            color(event.horse),
+            escape(name),
+            escape(&what),
+            event.position.map(|p| p.to_string()).unwrap_or_default(),
+            event.distance.map(|d| format!("{:.0}m", d)).unwrap_or_default(),
+        );
+    }
+    html.push_str("</table>");
+}
+
+fn position_chart(sim: &RaceSimulation) -> String {
+    let horses = sim.horse_count.max(2) as f32;
+    line_chart(sim, 1.0, horses, true, |frame, horse| {
+        let distance = frame.horses.get(horse)?.distance;
+        Some(
+            1.0 + frame
+                .horses
+                .iter()
+                .filter(|h| h.distance > distance)
+                .count() as f32,
+        )
+    })
+}
+
+fn speed_chart(sim: &RaceSimulation) -> String {
+    let speeds = sim
+        .frames
+        .iter()
+        .flat_map(|f| f.horses.iter().map(|h| h.speed))
+        .filter(|s| *s > 0.0);
+    let (min, max) = speeds.fold((f32::MAX, f32::MIN), |(lo, hi), s| (lo.min(s), hi.max(s)));
+    let (min, max) = if min < max {
+        (min.floor(), max.ceil())
+    } else {
+        (0.0, 30.0)
+    };
+    line_chart(sim, min, max, false, |frame, horse| {
+        frame.horses.get(horse).map(|h| h.speed)
+    })
+}
+
+// One polyline per horse over race time. With `invert`, `y_min` is drawn at
+// the top, as for positions where 1st is best.
+fn line_chart(
+    sim: &RaceSimulation,
+    y_min: f32,
+    y_max: f32,
+    invert: bool,
+    value: impl Fn(&simdata::Frame, usize) -> Option<f32>,
+) -> String {
+    let max_time = sim
+        .frames
+        .last()
+        .map(|f| f.time)
+        .filter(|t| *t > 0.0)
+        .unwrap_or(1.0);
+    let plot_w = CHART_WIDTH - CHART_PAD_LEFT - CHART_PAD_RIGHT;
+    let plot_h = CHART_HEIGHT - CHART_PAD_TOP - CHART_PAD_BOTTOM;
+    let x = |t: f32| CHART_PAD_LEFT + plot_w * t / max_time;
+    let y = |v: f32| {
+        let ratio = ((v - y_min) / (y_max - y_min)).clamp(0.0, 1.0);
+        let ratio = if invert { ratio } else { 1.0 - ratio };
+        CHART_PAD_TOP + plot_h * ratio
+    };
+
+    let mut svg = String::new();
+    let _ = write!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">",
+        w = CHART_WIDTH,
+        h = CHART_HEIGHT
+    );
+
+    let step = if y_max - y_min > 10.0 { 5.0 } else { 1.0 };
+    let mut tick = y_min;
+    while tick <= y_max {
+        let _ = write!(
+            svg,
+            "<line x1=\"{x0}\" y1=\"{y:.1}\" x2=\"{x1}\" y2=\"{y:.1}\" stroke=\"#e6e6e6\"/><text x=\"{tx}\" y=\"{ty:.1}\" text-anchor=\"end\">{v}</text>",
+            x0 = CHART_PAD_LEFT,
+            x1 = CHART_WIDTH - CHART_PAD_RIGHT,
+            y = y(tick),
+            tx = CHART_PAD_LEFT - 6.0,
+            ty = y(tick) + 4.0,
+            v = tick
+        );
+        tick += step;
+    }
+    let mut t = 0.0;
+    while t <= max_time {
+        let _ = write!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{}\" text-anchor=\"middle\">{}s</text>",
+            x(t),
+            CHART_HEIGHT - 8.0,
+            t
+        );
+        t += 20.0;
+    }
+
+    let stride = sim.frames.len().div_ceil(MAX_CHART_POINTS).max(1);
+    for horse in 0..sim.horse_count {
+        let points: Vec<String> = sim
+            .frames
+            .iter()
+            .step_by(stride)
+            .chain(sim.frames.last())
+            .filter_map(|f| Some(format!("{:.1},{:.1}", x(f.time), y(value(f, horse)?))))
+            .collect();
+        let _ = write!(
+            svg,
+            "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" points=\"{}\"/>",
+            color(horse),
+            points.join(" ")
+        );
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+fn ordinal(place: i32) -> String {
+    let suffix = match (place % 10, place % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", place, suffix)
+}
+
+fn color(horse: usize) -> String {
+    format!("hsl({}, 65%, 45%)", (horse * 137) % 360)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,377 @@
+use crate::config::{
+    relative_to_save_root, retention_interval_minutes, retention_rules, save_root, OutputFormat,
+    RetentionRule,
+};
+use crate::formats::{decode_captures, is_sidecar, sidecars};
+use crate::index;
+use crate::log;
+use crate::persistence;
+use chrono::{DateTime, Datelike, Local, Timelike};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// Folder of the save root that holds the monthly bundles. It is never
+/// scanned for captures or subject to retention itself.
+pub const ARCHIVE_DIR: &str = "Archive";
+
+struct Candidate {
+    path: PathBuf,
+    modified: SystemTime,
+    size: u64,
+    /// Reports, CSV rows and simulation sidecars of the races in the file,
+    /// archived along with it.
+    sidecars: Vec<PathBuf>,
+}
+
+/// Enforces the retention rules now and then every
+/// `retentionIntervalMinutes`. Returns right away when no rules are set.
+pub fn enforce_periodically() {
+    if retention_rules().is_empty() {
+        return;
+    }
+    let interval = Duration::from_secs(retention_interval_minutes().max(1) * 60);
+    loop {
+        enforce();
+        std::thread::sleep(interval);
+    }
+}
+
+pub fn enforce() {
+    for rule in retention_rules() {
+        let Some(folder) = rule_folder(rule) else {
+            log!(
+                "[Retention] Ignoring the rule for folder '{}': it must name a folder inside the save root.",
+                rule.folder
+            );
+            continue;
+        };
+        let expired = expired_files(rule, &folder);
+        if expired.is_empty() {
+            continue;
+        }
+
+        // Bundled by the month the file was last written.
+        let mut by_month: BTreeMap<String, Vec<Candidate>> = BTreeMap::new();
+        for candidate in expired {
+            let month = DateTime::<Local>::from(candidate.modified)
+                .format("%Y-%m")
+                .to_string();
+            by_month.entry(month).or_default().push(candidate);
+        }
+
+        for (month, files) in by_month {
+            let bundle = save_root()
+                .join(ARCHIVE_DIR)
+                .join(&rule.folder)
+                .join(format!("{}.zip", month));
+            match archive(&bundle, &files) {
+                Ok(()) => {
+                    log!(
+                        "[Retention] Archived {} file(s) from {} into {}",
+                        files.len(),
+                        folder.display(),
+                        bundle.display()
+                    );
+                }
+                Err(e) => {
+                    log!(
+                        "[Retention] Failed to archive into {}: {}",
+                        bundle.display(),
+                        e
+                    );
+                }
+            }
+        }
+    }
+}
+
+// Only plain relative folders are allowed, so that a rule can never reach
+// outside the save root or cover all of it.
+fn rule_folder(rule: &RetentionRule) -> Option<PathBuf> {
+    let folder = Path::new(rule.folder.trim());
+    let plain = folder.components().next().is_some()
+        && folder
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)));
+    plain.then(|| save_root().join(folder))
+}
+
+// Newest first; a file expires once it is older than the age limit, beyond
+// the count limit, or pushes the running total over the size limit.
+fn expired_files(rule: &RetentionRule, folder: &Path) -> Vec<Candidate> {
+    let live = persistence::veterans_file(save_root());
+    let today = format!("{}.ndjson", Local::now().format("%Y-%m-%d"));
+    let mut candidates: Vec<Candidate> = index::capture_files_in(folder)
+        .into_iter()
+        // Files still being written to: the "latest" veterans snapshot that
+        // each save replaces, and today's NDJSON files that get appended to.
+        .filter(|path| *path != live && path.file_name().is_none_or(|n| *n != *today))
+        .filter_map(|path| {
+            let meta = fs::metadata(&path).ok()?;
+            Some(Candidate {
+                modified: meta.modified().ok()?,
+                size: meta.len(),
+                sidecars: OutputFormat::from_path(&path)
+                    .map(|format| sidecars(&path, format))
+                    .unwrap_or_default(),
+                path,
+            })
+        })
+        .collect();
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.modified));
+
+    let now = SystemTime::now();
+    let max_age = Duration::from_secs(rule.max_age_days * 24 * 60 * 60);
+    let max_size = rule.max_size_mb * 1024 * 1024;
+    let mut total = 0;
+
+    candidates
+        .into_iter()
+        .enumerate()
+        .filter(|(i, c)| {
+            total += c.size;
+            let too_old = rule.max_age_days > 0
+                && now.duration_since(c.modified).unwrap_or_default() > max_age;
+            let too_many = rule.max_count > 0 && *i >= rule.max_count;
+            let too_big = rule.max_size_mb > 0 && total > max_size;
+            too_old || too_many || too_big
+        })
+        .map(|(_, c)| c)
+        .collect()
+}
+
+/// Adds `files` to the bundle, then marks them archived in the index and
+/// deletes them. The bundle is rewritten through a temporary copy so a crash
+/// never leaves it half written.
+fn archive(bundle: &Path, files: &[Candidate]) -> Result<(), String> {
+    let dir = bundle.parent().ok_or("bundle has no parent directory")?;
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+
+    let file_name = bundle
+        .file_name()
+        .ok_or("bundle has no file name")?
+        .to_string_lossy()
+        .to_string();
+    let tmp = dir.join(format!(".{}.{}.tmp", file_name, std::process::id()));
+
+    let result = write_bundle(bundle, &tmp, files);
+    let archived = match result {
+        Ok(archived) => archived,
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+    };
+    fs::rename(&tmp, bundle).map_err(|e| e.to_string())?;
+
+    let bundle_rel = relative_to_save_root(bundle);
+    for (candidate, captures) in files.iter().zip(archived) {
+        index::append_archived(&candidate.path, &captures, &bundle_rel);
+        for path in std::iter::once(&candidate.path).chain(&candidate.sidecars) {
+            if let Err(e) = fs::remove_file(path) {
+                log!(
+                    "[Retention] Archived but could not delete {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+type Captures = Vec<(Option<usize>, Value)>;
+
+// Returns the captures read from each file, in order, for the index update.
+fn write_bundle(bundle: &Path, tmp: &Path, files: &[Candidate]) -> Result<Vec<Captures>, String> {
+    let mut taken = HashSet::new();
+    let mut writer = if bundle.exists() {
+        fs::copy(bundle, tmp).map_err(|e| e.to_string())?;
+        let existing = ZipArchive::new(File::open(tmp).map_err(|e| e.to_string())?)
+            .map_err(|e| e.to_string())?;
+        taken.extend(
+            existing
+                .file_names()
+                .filter_map(|n| n.ok())
+                .map(|n| n.to_string()),
+        );
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(tmp)
+            .map_err(|e| e.to_string())?;
+        ZipWriter::new_append(f).map_err(|e| e.to_string())?
+    } else {
+        ZipWriter::new(File::create(tmp).map_err(|e| e.to_string())?)
+    };
+
+    let mut archived = Vec::new();
+    for candidate in files {
+        let bytes = fs::read(&candidate.path).map_err(|e| e.to_string())?;
+        let relative = relative_to_save_root(&candidate.path);
+        let name = entry_name(&relative, &taken);
+        add_entry(
+            &mut writer,
+            &mut taken,
+            name.clone(),
+            &bytes,
+            candidate.modified,
+        )?;
+
+        for sidecar in &candidate.sidecars {
+            let bytes = fs::read(sidecar).map_err(|e| e.to_string())?;
+            let modified = fs::metadata(sidecar)
+                .and_then(|m| m.modified())
+                .unwrap_or(candidate.modified);
+            let sidecar_name =
+                sidecar_entry_name(&relative_to_save_root(sidecar), &relative, &name);
+            add_entry(&mut writer, &mut taken, sidecar_name, &bytes, modified)?;
+        }
+
+        let captures = OutputFormat::from_path(&candidate.path)
+            .and_then(|format| decode_captures(format, &bytes).ok())
+            .unwrap_or_default();
+        archived.push(captures);
+    }
+
+    let f = writer.finish().map_err(|e| e.to_string())?;
+    f.sync_all().map_err(|e| e.to_string())?;
+    Ok(archived)
+}
+
+fn add_entry(
+    writer: &mut ZipWriter<File>,
+    taken: &mut HashSet<String>,
+    name: String,
+    bytes: &[u8],
+    modified: SystemTime,
+) -> Result<(), String> {
+    taken.insert(name.clone());
+    let mut options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .large_file(bytes.len() as u64 >= u32::MAX as u64);
+    if let Some(time) = zip_time(modified) {
+        options = options.last_modified_time(time);
+    }
+    writer
+        .start_file(name, options)
+        .map_err(|e| e.to_string())?;
+    writer.write_all(bytes).map_err(|e| e.to_string())
+}
+
+// A sidecar follows the entry name of its race, so it is renamed along with
+// it, e.g. `race.html` to `race-1.html`.
+fn sidecar_entry_name(sidecar: &str, capture: &str, capture_entry: &str) -> String {
+    let extension = OutputFormat::from_path(Path::new(capture))
+        .map(|f| format!(".{}", f.extension()))
+        .unwrap_or_default();
+    let stem = capture.strip_suffix(&extension).unwrap_or(capture);
+    let entry_stem = capture_entry
+        .strip_suffix(&extension)
+        .unwrap_or(capture_entry);
+    match sidecar.strip_prefix(stem) {
+        Some(rest) => format!("{}{}", entry_stem, rest),
+        None => sidecar.to_string(),
+    }
+}
+
+// Keeps entry names unique when a file of the same name is archived twice in
+// a month, e.g. `veterans.json` then `veterans-1.json`.
+fn entry_name(relative: &str, taken: &HashSet<String>) -> String {
+    if !taken.contains(relative) {
+        return relative.to_string();
+    }
+    let extension = OutputFormat::from_path(Path::new(relative))
+        .map(|f| f.extension())
+        .unwrap_or("");
+    let stem = relative
+        .strip_suffix(&format!(".{}", extension))
+        .unwrap_or(relative);
+    (1..)
+        .map(|i| format!("{}-{}.{}", stem, i, extension))
+        .find(|n| !taken.contains(n))
+        .expect("unbounded range always yields a free name")
+}
+
+fn zip_time(time: SystemTime) -> Option<zip::DateTime> {
+    let t = DateTime::<Local>::from(time);
+    zip::DateTime::from_date_and_time(
+        t.year().try_into().ok()?,
+        t.month() as u8,
+        t.day() as u8,
+        t.hour() as u8,
+        t.minute() as u8,
+        t.second() as u8,
+    )
+    .ok()
+}
+
+/// Calls `f` for every capture file stored in a bundle, with the entry's path
+/// relative to the save root, the bundle's path, the entry's modification
+/// time and the captures it holds. Used to rebuild the index.
+pub fn for_each_archived(mut f: impl FnMut(&str, &str, Option<String>, Captures)) {
+    let mut bundles = Vec::new();
+    collect_bundles(&save_root().join(ARCHIVE_DIR), &mut bundles);
+
+    for bundle in bundles {
+        let Ok(mut zip) = File::open(&bundle)
+            .map_err(|e| e.to_string())
+            .and_then(|file| ZipArchive::new(file).map_err(|e| e.to_string()))
+        else {
+            log!("[Retention] Could not open bundle {}", bundle.display());
+            continue;
+        };
+        let bundle_rel = relative_to_save_root(&bundle);
+
+        for i in 0..zip.len() {
+            let Ok(mut entry) = zip.by_index(i) else {
+                continue;
+            };
+            let Ok(name) = entry.name().map(|n| n.to_string()) else {
+                continue;
+            };
+            let path = Path::new(&name);
+            let Some(format) = OutputFormat::from_path(path).filter(|_| !is_sidecar(path)) else {
+                continue;
+            };
+            let modified = entry.last_modified().map(|t| {
+                format!(
+                    "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+                    t.year(),
+                    t.month(),
+                    t.day(),
+                    t.hour(),
+                    t.minute(),
+                    t.second()
+                )
+            });
+            let mut bytes = Vec::new();
+            if entry.read_to_end(&mut bytes).is_err() {
+                continue;
+            }
+            if let Ok(captures) = decode_captures(format, &bytes) {
+                f(&name, &bundle_rel, modified, captures);
+            }
+        }
+    }
+}
+
+fn collect_bundles(dir: &Path, bundles: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_bundles(&path, bundles);
+        } else if path.extension().is_some_and(|e| e == "zip") {
+            bundles.push(path);
+        }
+    }
+}
@@ -0,0 +1,329 @@
+use crate::config::{json_schemas, save_root};
+use crate::envelope::{game_assembly_fingerprint, CaptureType};
+use crate::log;
+use crate::persistence::write_atomic;
+use crate::reflection::CAPTURED_ENUMS;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Folder of the save root the schemas are written to. It is never scanned
+/// for captures.
+pub const SCHEMA_DIR: &str = "schemas";
+
+// Strings longer than this, or members with more distinct values than this,
+// are free text rather than enum names.
+const MAX_ENUM_VALUE_LEN: usize = 64;
+const MAX_ENUM_VALUES: usize = 64;
+
+// Envelope members that hold a new value on every capture. Their values are
+// never collected as enum candidates.
+const PER_CAPTURE_MEMBERS: [&[&str]; 3] = [
+    &["capturedAt", "utc"],
+    &["capturedAt", "local"],
+    &["contentHash"],
+];
+
+/// What has been observed at one position of the captured documents, merged
+/// over every capture of a game build.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct Shape {
+    types: BTreeSet<String>,
+    /// Object members in the order they were first seen.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    properties: Vec<(String, Shape)>,
+    /// Members present in every object seen here; `None` until the first one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    required: Option<BTreeSet<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    items: Option<Box<Shape>>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    string_values: BTreeSet<String>,
+    #[serde(default)]
+    free_text: bool,
+    /// Captured enum whose names cover every string seen here, with its names.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    enum_type: Option<(String, Vec<String>)>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct Observed {
+    fingerprint: String,
+    shape: Shape,
+}
+
+static OBSERVED: Mutex<Option<HashMap<&'static str, Observed>>> = Mutex::new(None);
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+impl Shape {
+    /// Merges `value` in. Returns whether the shape changed.
+    fn observe(&mut self, value: &Value) -> bool {
+        let mut changed = self.types.insert(type_name(value).to_string());
+        match value {
+            Value::String(s) if !self.free_text => {
+                if s.len() > MAX_ENUM_VALUE_LEN {
+                    self.free_text = true;
+                    self.string_values.clear();
+                    changed = true;
+                } else if self.string_values.insert(s.clone()) {
+                    if self.string_values.len() > MAX_ENUM_VALUES {
+                        self.free_text = true;
+                        self.string_values.clear();
+                    }
+                    changed = true;
+                }
+            }
+            Value::Object(map) => {
+                match &mut self.required {
+                    Some(required) => {
+                        let before = required.len();
+                        required.retain(|k| map.contains_key(k));
+                        changed |= required.len() != before;
+                    }
+                    None => {
+                        self.required = Some(map.keys().cloned().collect());
+                        changed = true;
+                    }
+                }
+                for (key, v) in map {
+                    let i = match self.properties.iter().position(|(k, _)| k == key) {
+                        Some(i) => i,
+                        None => {
+                            self.properties.push((key.clone(), Shape::default()));
+                            changed = true;
+                            self.properties.len() - 1
+                        }
+                    };
+                    changed |= self.properties[i].1.observe(v);
+                }
+            }
+            Value::Array(arr) => {
+                for v in arr {
+                    changed |= self.items.get_or_insert_with(Default::default).observe(v);
+                }
+            }
+            _ => {}
+        }
+        changed
+    }
+
+    /// Marks the member at `path` as free text, when it has been seen.
+    fn mark_free_text(&mut self, path: &[&str]) {
+        let Some((first, rest)) = path.split_first() else {
+            if !self.free_text {
+                self.free_text = true;
+                self.string_values.clear();
+            }
+            return;
+        };
+        if let Some((_, child)) = self.properties.iter_mut().find(|(k, _)| k == first) {
+            child.mark_free_text(rest);
+        }
+    }
+
+    /// Links string members to the captured enum all their values belong to,
+    /// preferring the smallest one. A previous match is kept when the enum
+    /// has not been captured this session.
+    fn match_enums(&mut self, enums: &Map<String, Value>) {
+        if !self.free_text && !self.string_values.is_empty() {
+            let matched = enums
+                .iter()
+                .filter_map(|(name, values)| Some((name, values.as_object()?)))
+                .filter(|(_, values)| self.string_values.iter().all(|s| values.contains_key(s)))
+                .min_by_key(|(_, values)| values.len());
+            match matched {
+                Some((name, values)) => {
+                    self.enum_type = Some((name.clone(), values.keys().cloned().collect()));
+                }
+                None => {
+                    let still_covered = self.enum_type.as_ref().is_some_and(|(_, names)| {
+                        self.string_values.iter().all(|s| names.contains(s))
+                    });
+                    if !still_covered {
+                        self.enum_type = None;
+                    }
+                }
+            }
+        }
+        for (_, child) in &mut self.properties {
+            child.match_enums(enums);
+        }
+        if let Some(items) = &mut self.items {
+            items.match_enums(enums);
+        }
+    }
+
+    fn to_schema(&self) -> Map<String, Value> {
+        let mut schema = Map::new();
+
+        let mut types: Vec<&str> = self.types.iter().map(|t| t.as_str()).collect();
+        if types.contains(&"number") {
+            types.retain(|t| *t != "integer");
+        }
+        match types.as_slice() {
+            [] => {}
+            [single] => {
+                schema.insert("type".to_string(), Value::from(*single));
+            }
+            _ => {
+                schema.insert("type".to_string(), Value::from(types.clone()));
+            }
+        }
+
+        // Only for members that never held anything but names, since enum
+        // values without a name are dumped as numbers.
+        let names_only = self.types.iter().all(|t| t == "string" || t == "null");
+        if let Some((name, values)) = &self.enum_type {
+            if !self.free_text && names_only {
+                let mut values: Vec<Value> = values.iter().cloned().map(Value::String).collect();
+                if self.types.contains("null") {
+                    values.push(Value::Null);
+                }
+                schema.insert(
+                    "description".to_string(),
+                    Value::String(format!("Name of a value of the game's `{}` enum.", name)),
+                );
+                schema.insert("enum".to_string(), Value::Array(values));
+            }
+        }
+
+        if !self.properties.is_empty() {
+            let properties = self
+                .properties
+                .iter()
+                .map(|(k, child)| (k.clone(), Value::Object(child.to_schema())))
+                .collect();
+            schema.insert("properties".to_string(), Value::Object(properties));
+        }
+        if let Some(required) = self.required.as_ref().filter(|r| !r.is_empty()) {
+            // Listed in member order rather than alphabetically.
+            let required: Vec<Value> = self
+                .properties
+                .iter()
+                .filter(|(k, _)| required.contains(k))
+                .map(|(k, _)| Value::String(k.clone()))
+                .collect();
+            schema.insert("required".to_string(), Value::Array(required));
+        }
+        if let Some(items) = &self.items {
+            schema.insert("items".to_string(), Value::Object(items.to_schema()));
+        }
+        schema
+    }
+}
+
+fn schema_dir() -> PathBuf {
+    save_root().join(SCHEMA_DIR)
+}
+
+fn observed_path(name: &str) -> PathBuf {
+    schema_dir().join(format!(".observed-{}.json", name))
+}
+
+fn load(name: &str) -> Observed {
+    fs::read(observed_path(name))
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+/// Merges a capture into the inferred schema of its type and rewrites
+/// `schemas/<captureType>.schema.json` when something new was seen. The
+/// inference starts over when the game assembly fingerprint changes, so the
+/// schema always describes the running game build. Runs on its own thread.
+pub fn observe(capture_type: CaptureType, output: Value) {
+    if !json_schemas() {
+        return;
+    }
+    std::thread::spawn(move || merge(capture_type, &output));
+}
+
+fn merge(capture_type: CaptureType, output: &Value) {
+    let name = capture_type.as_str();
+    let mut guard = OBSERVED.lock().unwrap_or_else(|e| e.into_inner());
+    let observed = guard
+        .get_or_insert_with(HashMap::new)
+        .entry(name)
+        .or_insert_with(|| load(name));
+
+    let mut changed = false;
+    let fingerprint = game_assembly_fingerprint();
+    if observed.fingerprint != fingerprint {
+        if !observed.fingerprint.is_empty() {
+            log!(
+                "[Schema] Game assembly changed, regenerating the {} schema.",
+                name
+            );
+        }
+        *observed = Observed {
+            fingerprint: fingerprint.to_string(),
+            shape: Shape::default(),
+        };
+        changed = true;
+    }
+    changed |= observed.shape.observe(output);
+    for path in PER_CAPTURE_MEMBERS {
+        observed.shape.mark_free_text(path);
+    }
+    if !changed {
+        return;
+    }
+
+    if let Some(enums) = CAPTURED_ENUMS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+    {
+        observed.shape.match_enums(enums);
+    }
+
+    if let Err(e) = write(name, observed) {
+        log!("[Schema] Failed to write the {} schema: {}", name, e);
+    }
+}
+
+fn write(name: &str, observed: &Observed) -> Result<(), String> {
+    fs::create_dir_all(schema_dir()).map_err(|e| e.to_string())?;
+
+    let mut schema = Map::new();
+    schema.insert(
+        "$schema".to_string(),
+        Value::from("https://json-schema.org/draft/2020-12/schema"),
+    );
+    schema.insert(
+        "title".to_string(),
+        Value::String(format!("horseACT {} capture", name)),
+    );
+    schema.insert(
+        "x-gameAssemblyFingerprint".to_string(),
+        Value::String(observed.fingerprint.clone()),
+    );
+    schema.insert(
+        "x-pluginVersion".to_string(),
+        Value::from(env!("CARGO_PKG_VERSION")),
+    );
+    schema.extend(observed.shape.to_schema());
+
+    let bytes = serde_json::to_vec_pretty(&schema).map_err(|e| e.to_string())?;
+    write_atomic(&schema_dir().join(format!("{}.schema.json", name)), &bytes)
+        .map_err(|e| e.to_string())?;
+
+    let state = serde_json::to_vec(observed).map_err(|e| e.to_string())?;
+    write_atomic(&observed_path(name), &state).map_err(|e| e.to_string())
+}
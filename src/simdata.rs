@@ -0,0 +1,438 @@
+use crate::log;
+use crate::persistence::write_atomic;
+use crate::timeline;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use serde::Serialize;
+use serde_json::Value;
+use std::io::Read;
+use std::path::Path;
+
+/// Race simulation decoded from `<SimDataBase64>k__BackingField`.
+///
+/// The binary layout is little-endian and made of length-prefixed blocks: a
+/// header, race-level sizes, one record per frame with an entry per horse,
+/// one result per horse and a list of events. Record sizes are read from the
+/// data so that fields appended by newer game versions are skipped over.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RaceSimulation {
+    pub header: Header,
+    pub distance_diff_max: f32,
+    pub horse_count: usize,
+    pub horses: Vec<HorseResult>,
+    pub frames: Vec<Frame>,
+    pub events: Vec<Event>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Header {
+    pub max_length: i32,
+    pub version: i32,
+}
+
+/// Per-horse static result, in gate order (the horse index used by frames
+/// and events).
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HorseResult {
+    pub finish_order: i32,
+    pub finish_time: f32,
+    pub finish_diff_time: f32,
+    pub start_delay_time: f32,
+    pub guts_order: u8,
+    pub wiz_order: u8,
+    pub last_spurt_start_distance: f32,
+    pub running_style: u8,
+    pub defeat: i32,
+    pub finish_time_raw: f32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Frame {
+    pub time: f32,
+    pub horses: Vec<HorseFrame>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HorseFrame {
+    /// Metres from the start.
+    pub distance: f32,
+    pub lane_position: u16,
+    /// Metres per second.
+    pub speed: f32,
+    pub hp: u16,
+    pub temptation_mode: i8,
+    /// Index of the horse blocking this one, or -1.
+    pub block_front_horse_index: i8,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Event {
+    pub frame_time: f32,
+    #[serde(rename = "type")]
+    pub kind: i8,
+    pub params: Vec<i32>,
+}
+
+// Bytes of the fields read from each record; records may be longer.
+const HORSE_FRAME_LEN: usize = 12;
+const HORSE_RESULT_LEN: usize = 31;
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let end = self.pos + N;
+        let slice = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or_else(|| format!("unexpected end of data at offset {}", self.pos))?;
+        self.pos = end;
+        Ok(slice.try_into().expect("slice has length N"))
+    }
+
+    fn seek(&mut self, pos: usize) -> Result<(), String> {
+        if pos > self.bytes.len() {
+            return Err(format!("offset {} is past the end of data", pos));
+        }
+        self.pos = pos;
+        Ok(())
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), String> {
+        self.seek(self.pos + len)
+    }
+
+    fn i8(&mut self) -> Result<i8, String> {
+        Ok(i8::from_le_bytes(self.take()?))
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(u8::from_le_bytes(self.take()?))
+    }
+
+    fn i16(&mut self) -> Result<i16, String> {
+        Ok(i16::from_le_bytes(self.take()?))
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_le_bytes(self.take()?))
+    }
+
+    fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_le_bytes(self.take()?))
+    }
+
+    // Sizes and counts are stored as signed integers.
+    fn size(&mut self, what: &str) -> Result<usize, String> {
+        let n = self.i32()?;
+        usize::try_from(n).map_err(|_| format!("negative {}: {}", what, n))
+    }
+}
+
+/// Base64-decodes, decompresses and parses a `SimDataBase64` string.
+pub fn decode(sim_data_base64: &str) -> Result<RaceSimulation, String> {
+    let compressed = STANDARD
+        .decode(sim_data_base64.trim())
+        .map_err(|e| format!("base64: {}", e))?;
+    let mut bytes = Vec::new();
+    let inflated = if compressed.starts_with(&[0x1f, 0x8b]) {
+        GzDecoder::new(compressed.as_slice()).read_to_end(&mut bytes)
+    } else {
+        ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut bytes)
+    };
+    inflated.map_err(|e| format!("decompress: {}", e))?;
+    parse(&bytes)
+}
+
+/// Parses the decompressed binary race-simulate format.
+pub fn parse(bytes: &[u8]) -> Result<RaceSimulation, String> {
+    let mut r = Reader::new(bytes);
+
+    let max_length = r.i32()?;
+    let version = r.i32()?;
+    // `max_length` counts the header bytes after itself.
+    r.seek(4 + usize::try_from(max_length).map_err(|_| "negative header length")?)?;
+
+    let distance_diff_max = r.f32()?;
+    let horse_count = r.size("horse count")?;
+    // Every horse has a result record of at least `HORSE_RESULT_LEN` bytes,
+    // so a count the data cannot hold is corrupt and must not size buffers.
+    if horse_count > bytes.len() / HORSE_RESULT_LEN {
+        return Err(format!(
+            "horse count {} does not fit in the data",
+            horse_count
+        ));
+    }
+    let horse_frame_size = r.size("horse frame size")?;
+    let horse_result_size = r.size("horse result size")?;
+    let padding = r.size("padding")?;
+    r.skip(padding)?;
+
+    let frame_count = r.size("frame count")?;
+    let frame_size = r.size("frame size")?;
+    // Smaller records than the fields read below would mean a layout this
+    // parser does not know; refuse rather than misread.
+    if horse_frame_size < HORSE_FRAME_LEN
+        || horse_result_size < HORSE_RESULT_LEN
+        || frame_size < 4 + horse_count * horse_frame_size
+    {
+        return Err(format!(
+            "unexpected record sizes (frame {}, horse frame {}, horse result {})",
+            frame_size, horse_frame_size, horse_result_size
+        ));
+    }
+    let mut frames = Vec::with_capacity(frame_count.min(bytes.len()));
+    for _ in 0..frame_count {
+        let start = r.pos;
+        let time = r.f32()?;
+        let mut horses = Vec::with_capacity(horse_count);
+        for _ in 0..horse_count {
+            let horse_start = r.pos;
+            horses.push(HorseFrame {
+                distance: r.f32()?,
+                lane_position: r.u16()?,
+                speed: f32::from(r.u16()?) / 100.0,
+                hp: r.u16()?,
+                temptation_mode: r.i8()?,
+                block_front_horse_index: r.i8()?,
+            });
+            r.seek(horse_start + horse_frame_size)?;
+        }
+        frames.push(Frame { time, horses });
+        r.seek(start + frame_size)?;
+    }
+
+    let padding = r.size("padding")?;
+    r.skip(padding)?;
+
+    let mut horses = Vec::with_capacity(horse_count);
+    for _ in 0..horse_count {
+        let start = r.pos;
+        horses.push(HorseResult {
+            finish_order: r.i32()?,
+            finish_time: r.f32()?,
+            finish_diff_time: r.f32()?,
+            start_delay_time: r.f32()?,
+            guts_order: r.u8()?,
+            wiz_order: r.u8()?,
+            last_spurt_start_distance: r.f32()?,
+            running_style: r.u8()?,
+            defeat: r.i32()?,
+            finish_time_raw: r.f32()?,
+        });
+        r.seek(start + horse_result_size)?;
+    }
+
+    let padding = r.size("padding")?;
+    r.skip(padding)?;
+
+    let event_count = r.size("event count")?;
+    let mut events = Vec::with_capacity(event_count.min(bytes.len()));
+    for _ in 0..event_count {
+        let size = usize::try_from(r.i16()?).map_err(|_| "negative event size")?;
+        let start = r.pos;
+        let frame_time = r.f32()?;
+        let kind = r.i8()?;
+        let param_count = usize::try_from(r.i8()?).map_err(|_| "negative param count")?;
+        if size < 6 + 4 * param_count {
+            return Err(format!("event of {} bytes is too short", size));
+        }
+        let params = (0..param_count)
+            .map(|_| r.i32())
+            .collect::<Result<Vec<_>, _>>()?;
+        events.push(Event {
+            frame_time,
+            kind,
+            params,
+        });
+        r.seek(start + size)?;
+    }
+
+    Ok(RaceSimulation {
+        header: Header {
+            max_length,
+            version,
+        },
+        distance_diff_max,
+        horse_count,
+        horses,
+        frames,
+        events,
+    })
+}
+
+impl RaceSimulation {
+    /// One row per horse per frame.
+    pub fn frames_csv(&self) -> String {
+        let mut out = String::from(
+            "frame,time,horse,distance,lanePosition,speed,hp,temptationMode,blockFrontHorseIndex\r\n",
+        );
+        for (i, frame) in self.frames.iter().enumerate() {
+            for (h, horse) in frame.horses.iter().enumerate() {
+                out.push_str(&format!(
+                    "{},{},{},{},{},{},{},{},{}\r\n",
+                    i,
+                    frame.time,
+                    h,
+                    horse.distance,
+                    horse.lane_position,
+                    horse.speed,
+                    horse.hp,
+                    horse.temptation_mode,
+                    horse.block_front_horse_index
+                ));
+            }
+        }
+        out
+    }
+}
+
+/// Decodes the simulation of a saved race and writes it next to the race
+/// file: `<base>.sim.json` with the simulation and its `timeline`, and/or
+/// `<base>.sim.csv` with the frames plus `<base>.timeline.csv`.
+pub fn write_sidecars(base: &Path, output: &Value, json: bool, csv: bool, tag: &str) {
+    let data = output.get("data").unwrap_or(output);
+    let Some(encoded) = data
+        .get("<SimDataBase64>k__BackingField")
+        .and_then(|v| v.as_str())
+    else {
+        return;
+    };
+
+    let sim = match decode(encoded) {
+        Ok(sim) => sim,
+        Err(e) => {
+            log!("[{}] Failed to decode simulation data: {}", tag, e);
+            return;
+        }
+    };
+    let events = timeline::build(&sim, &timeline::horse_names(data));
+
+    let mut written = Vec::new();
+    if json {
+        let doc = serde_json::to_value(&sim).and_then(|mut doc| {
+            doc["timeline"] = serde_json::to_value(&events)?;
+            serde_json::to_vec_pretty(&doc)
+        });
+        written.push(("sim.json", doc.map_err(|e| e.to_string())));
+    }
+    if csv {
+        written.push(("sim.csv", Ok(sim.frames_csv().into_bytes())));
+        written.push(("timeline.csv", Ok(timeline::to_csv(&events).into_bytes())));
+    }
+
+    for (suffix, bytes) in written {
+        let mut path = base.as_os_str().to_owned();
+        path.push(format!(".{}", suffix));
+        let path = Path::new(&path);
+        match bytes.and_then(|b| write_atomic(path, &b).map_err(|e| e.to_string())) {
+            Ok(()) => {
+                log!("[{}] Saved simulation to: {}", tag, path.display());
+            }
+            Err(e) => {
+                log!("[{}] Failed to write {}: {}", tag, path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    // Synthetic payload built to the documented layout, not a captured race:
+    // three horses over four frames with a skill activation and one other
+    // event, gzipped and base64-encoded like the `SimDataBase64` member.
+    const SAMPLE: &str = include_str!("../tests/fixtures/sim_data.b64");
+
+    fn inflated_sample() -> Vec<u8> {
+        let compressed = STANDARD.decode(SAMPLE.trim()).unwrap();
+        let mut bytes = Vec::new();
+        GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut bytes)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn decodes_gzip_sample() {
+        let sim = decode(SAMPLE).unwrap();
+
+        assert_eq!(sim.header.max_length, 4);
+        assert_eq!(sim.header.version, 10000006);
+        assert_eq!(sim.distance_diff_max, 2.25);
+        assert_eq!(sim.horse_count, 3);
+        assert_eq!(sim.horses.len(), 3);
+
+        let orders: Vec<i32> = sim.horses.iter().map(|h| h.finish_order).collect();
+        assert_eq!(orders, [1, 2, 0]);
+        let times: Vec<f32> = sim.horses.iter().map(|h| h.finish_time).collect();
+        assert_eq!(times, [73.4, 73.6, 73.1]);
+        assert_eq!(sim.horses[2].finish_time_raw, 73.12);
+        assert_eq!(sim.horses[0].running_style, 2);
+
+        assert_eq!(sim.frames.len(), 4);
+        assert!(sim.frames.iter().all(|f| f.horses.len() == 3));
+        assert_eq!(sim.frames[3].time, 3.0);
+        assert_eq!(sim.frames[1].horses[2].distance, 18.9);
+        assert_eq!(sim.frames[1].horses[2].speed, 18.9);
+        assert_eq!(sim.frames[3].horses[0].block_front_horse_index, 2);
+
+        assert_eq!(sim.events.len(), 2);
+        assert_eq!(sim.events[0].kind, 3);
+        assert_eq!(sim.events[0].params, [0, 10001]);
+    }
+
+    #[test]
+    fn decodes_zlib() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&inflated_sample()).unwrap();
+        let encoded = STANDARD.encode(encoder.finish().unwrap());
+
+        let sim = decode(&encoded).unwrap();
+        assert_eq!(sim.horse_count, 3);
+        assert_eq!(sim.frames.len(), 4);
+        assert_eq!(sim.horses[2].finish_order, 0);
+    }
+
+    #[test]
+    fn truncated_input_is_an_error() {
+        let bytes = inflated_sample();
+        for len in [0, 6, 30, bytes.len() / 2, bytes.len() - 1] {
+            assert!(parse(&bytes[..len]).is_err(), "parsed {} bytes", len);
+        }
+
+        let encoded = SAMPLE.trim();
+        assert!(decode(&encoded[..encoded.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn oversized_horse_count_is_an_error() {
+        let mut bytes = inflated_sample();
+        // The horse count follows the header and `distanceDiffMax`.
+        bytes[12..16].copy_from_slice(&i32::MAX.to_le_bytes());
+        let err = parse(&bytes).err().unwrap();
+        assert!(err.contains("does not fit"), "{}", err);
+    }
+}
@@ -0,0 +1,332 @@
+use crate::config::{
+    html_report, output_format, relative_to_save_root, sim_data_sidecar, sinks, OutputFormat,
+    SimDataSidecar, SinkConfig, SinkKind,
+};
+use crate::csv_export;
+use crate::envelope::CaptureType;
+use crate::formats::{append_line, sidecar_base};
+use crate::index;
+use crate::log;
+use crate::parquet_export;
+use crate::persistence::{output_path, resolved_path, write_atomic, write_capture};
+use crate::report;
+use crate::simdata;
+use crate::sqlite;
+use crate::template::TemplateContext;
+use serde_json::Value;
+use std::fs;
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Everything a sink needs to know about one enveloped capture.
+pub struct Capture<'a> {
+    pub capture_type: CaptureType,
+    /// Raw `<RaceType>` value for races, used by `raceTypes` filters.
+    pub race_type: Option<&'a str>,
+    /// Endpoint name for API responses, used as the HTTP ingest endpoint.
+    pub endpoint: Option<&'a str>,
+    pub output: &'a Value,
+    pub ctx: &'a TemplateContext,
+    pub file_template: &'a str,
+    /// Overwrite an existing file of the same name (keeping a `.bak`)
+    /// instead of picking a unique name.
+    pub replace_existing: bool,
+    pub tag: &'a str,
+}
+
+impl SinkConfig {
+    // API responses may hold fields of the endpoint that are not meant to be
+    // shared, so only sinks that list them get them.
+    fn accepts(&self, capture: &Capture) -> bool {
+        let type_ok = (self.capture_types.is_empty()
+            && capture.capture_type != CaptureType::ApiResponse)
+            || self
+                .capture_types
+                .iter()
+                .any(|t| t == capture.capture_type.as_str());
+        let race_ok = self.race_types.is_empty()
+            || capture
+                .race_type
+                .is_some_and(|rt| self.race_types.iter().any(|t| t == rt));
+        type_ok && race_ok
+    }
+}
+
+/// Whether a directory sink that takes `capture` lacks the file it would
+/// replace, e.g. because it was deleted. NDJSON captures are appended and
+/// never replace a file.
+pub fn replaced_file_missing(capture: &Capture) -> bool {
+    let format = output_format(capture.capture_type);
+    format != OutputFormat::Ndjson
+        && sinks()
+            .iter()
+            .filter(|s| matches!(s.kind, SinkKind::Directory) && s.accepts(capture))
+            .any(|s| {
+                let root = Path::new(&s.path);
+                !resolved_path(root, capture.file_template, capture.ctx, format.extension())
+                    .exists()
+            })
+}
+
+/// Routes a capture to every configured sink whose filters match. Local sinks
+/// run in order on the calling thread; network sinks run on their own threads
+/// afterwards so they never hold up the game. Returns the location of the
+/// first successful local write.
+pub fn dispatch(capture: &Capture) -> Option<String> {
+    let matching: Vec<&SinkConfig> = sinks().iter().filter(|s| s.accepts(capture)).collect();
+    if matching.is_empty() {
+        log!("[{}] No output sink accepts this capture.", capture.tag);
+        return None;
+    }
+
+    let mut first_location = None;
+    let mut saved_file = None;
+
+    for sink in matching.iter().filter(|s| !runs_in_background(s)) {
+        let location = match sink.kind {
+            SinkKind::Directory => write_to_directory(sink, capture).map(|(path, line)| {
+                let mut location = relative_to_save_root(&path);
+                if let Some(line) = line {
+                    location.push_str(&format!("#L{}", line));
+                }
+                saved_file.get_or_insert((path, line));
+                location
+            }),
+            SinkKind::Sqlite => write_to_sqlite(sink, capture),
+            SinkKind::Http | SinkKind::Socket | SinkKind::Parquet => None,
+        };
+        if first_location.is_none() {
+            first_location = location;
+        }
+    }
+
+    for sink in matching.iter().filter(|s| runs_in_background(s)) {
+        let sink = (*sink).clone();
+        let output = capture.output.clone();
+        let capture_type = capture.capture_type;
+        let endpoint = capture
+            .endpoint
+            .unwrap_or(capture.capture_type.as_str())
+            .to_string();
+        let tag = capture.tag.to_string();
+        let saved_file = saved_file.clone();
+        std::thread::spawn(move || match sink.kind {
+            SinkKind::Http => send_http(&sink, capture_type, &endpoint, &output, saved_file, &tag),
+            SinkKind::Socket => send_socket(&sink, &output, &tag),
+            SinkKind::Parquet => parquet_export::append_race(
+                Path::new(&sink.path),
+                &output,
+                saved_file.as_ref().map(|(path, _)| path.as_path()),
+                &tag,
+            ),
+            SinkKind::Directory | SinkKind::Sqlite => {}
+        });
+    }
+
+    first_location
+}
+
+// Network sinks, and Parquet which hands races to its own worker.
+fn runs_in_background(sink: &SinkConfig) -> bool {
+    matches!(
+        sink.kind,
+        SinkKind::Http | SinkKind::Socket | SinkKind::Parquet
+    )
+}
+
+/// Writes the capture in the configured format and indexes it. Returns the
+/// file written and, for NDJSON, the line the capture was appended as.
+fn write_to_directory(sink: &SinkConfig, capture: &Capture) -> Option<(PathBuf, Option<usize>)> {
+    let root = Path::new(&sink.path);
+    let format = output_format(capture.capture_type);
+
+    if format == OutputFormat::Ndjson {
+        // One file per day next to where the template would have put the
+        // capture, e.g. `Room match/2026-01-31.ndjson`.
+        let templated = output_path(
+            root,
+            capture.file_template,
+            capture.ctx,
+            format.extension(),
+            false,
+            capture.tag,
+        )?;
+        let path = templated.with_file_name(format!(
+            "{}.{}",
+            capture.ctx.captured_at().format("%Y-%m-%d"),
+            format.extension()
+        ));
+        return match append_line(&path, capture.output) {
+            Ok(line) => {
+                log!(
+                    "[{}] Appended to: {} (line {})",
+                    capture.tag,
+                    path.display(),
+                    line
+                );
+                index::append(&path, Some(line), capture.output);
+                export_race_extras(root, &path, Some(line), format, capture);
+                Some((path, Some(line)))
+            }
+            Err(e) => {
+                log!(
+                    "[{}] Failed to append to {}: {}",
+                    capture.tag,
+                    path.display(),
+                    e
+                );
+                None
+            }
+        };
+    }
+
+    let path = output_path(
+        root,
+        capture.file_template,
+        capture.ctx,
+        format.extension(),
+        !capture.replace_existing,
+        capture.tag,
+    )?;
+
+    if capture.replace_existing && path.exists() && !back_up(&path, capture.tag) {
+        return None;
+    }
+
+    if !write_capture(&path, capture.output, format, capture.tag) {
+        return None;
+    }
+    index::append(&path, None, capture.output);
+    export_race_extras(root, &path, None, format, capture);
+    Some((path, None))
+}
+
+// CSV rows, decoded simulation sidecars and HTML reports, written next to
+// saved races. Reports and sidecars are built on their own threads.
+fn export_race_extras(
+    root: &Path,
+    saved_file: &Path,
+    line: Option<usize>,
+    format: OutputFormat,
+    capture: &Capture,
+) {
+    if capture.capture_type != CaptureType::Race {
+        return;
+    }
+
+    csv_export::export_race(
+        root,
+        saved_file,
+        format.extension(),
+        format == OutputFormat::Ndjson,
+        capture.race_type,
+        capture.output,
+        capture.tag,
+    );
+
+    let base = sidecar_base(saved_file, format, line);
+    if html_report() {
+        let base = base.clone();
+        let output = capture.output.clone();
+        let tag = capture.tag.to_string();
+        std::thread::spawn(move || report::write_report(&base, &output, &tag));
+    }
+
+    let (json, csv) = match sim_data_sidecar() {
+        SimDataSidecar::Off => return,
+        SimDataSidecar::Json => (true, false),
+        SimDataSidecar::Csv => (false, true),
+        SimDataSidecar::Both => (true, true),
+    };
+    let output = capture.output.clone();
+    let tag = capture.tag.to_string();
+    std::thread::spawn(move || simdata::write_sidecars(&base, &output, json, csv, &tag));
+}
+
+// Keeps the file about to be replaced as `<name>.bak` until the next write.
+fn back_up(path: &Path, tag: &str) -> bool {
+    let mut bak = path.as_os_str().to_owned();
+    bak.push(".bak");
+    match fs::read(path) {
+        Ok(previous) => {
+            if let Err(e) = write_atomic(Path::new(&bak), &previous) {
+                log!("[{}] Failed to back up {}: {}", tag, path.display(), e);
+                return false;
+            }
+            true
+        }
+        Err(e) => {
+            log!("[{}] Failed to read {}: {}", tag, path.display(), e);
+            false
+        }
+    }
+}
+
+fn write_to_sqlite(sink: &SinkConfig, capture: &Capture) -> Option<String> {
+    match sqlite::insert(Path::new(&sink.path), capture.capture_type, capture.output) {
+        Ok(id) => {
+            log!("[{}] Saved to SQLite as capture {}", capture.tag, id);
+            Some(format!("sqlite:{}", id))
+        }
+        Err(e) => {
+            log!("[{}] Failed to save to SQLite: {}", capture.tag, e);
+            None
+        }
+    }
+}
+
+// Signed, gzipped ingest protocol, with the capture type as the endpoint name.
+// API responses keep their own endpoint and are sent without the envelope, as
+// the server's endpoint configs expect.
+fn send_http(
+    sink: &SinkConfig,
+    capture_type: CaptureType,
+    endpoint: &str,
+    output: &Value,
+    saved_file: Option<(PathBuf, Option<usize>)>,
+    tag: &str,
+) {
+    if sink.url.is_empty() {
+        log!("[{}] HTTP sink skipped: no URL configured", tag);
+        return;
+    }
+
+    let payload = match capture_type {
+        CaptureType::ApiResponse => &output["data"],
+        _ => output,
+    };
+    let ok = crate::api::send(&sink.url, &sink.api_key, endpoint, payload);
+    if let Some((path, line)) = saved_file {
+        let status = if ok { "uploaded" } else { "uploadFailed" };
+        index::append_with_status(&path, line, output, status);
+    }
+}
+
+fn send_socket(sink: &SinkConfig, output: &Value, tag: &str) {
+    let addr = match sink.address.to_socket_addrs().map(|mut a| a.next()) {
+        Ok(Some(addr)) => addr,
+        _ => {
+            log!("[{}] Invalid socket sink address '{}'", tag, sink.address);
+            return;
+        }
+    };
+
+    let result = TcpStream::connect_timeout(&addr, SOCKET_TIMEOUT).and_then(|mut stream| {
+        stream.set_write_timeout(Some(SOCKET_TIMEOUT))?;
+        writeln!(stream, "{}", output)?;
+        stream.flush()
+    });
+
+    match result {
+        Ok(()) => {
+            log!("[{}] Sent to socket {}", tag, addr);
+        }
+        Err(e) => {
+            log!("[{}] Failed to send to socket {}: {}", tag, addr, e);
+        }
+    }
+}
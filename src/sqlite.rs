@@ -0,0 +1,298 @@
+use crate::envelope::CaptureType;
+use crate::log;
+use crate::lookup::value_to_plain_string;
+use crate::models::{RaceInfo, TeamTrialResult, TrainedChara};
+use rusqlite::{params, Connection, Transaction};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// One connection per database file, since several sinks may point at
+// different databases.
+static CONNECTIONS: Mutex<Option<HashMap<PathBuf, Connection>>> = Mutex::new(None);
+
+// Each entry upgrades the schema by one version; `PRAGMA user_version` holds
+// the number of migrations applied. Never edit a migration once released,
+// append a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // 1: initial schema
+    "CREATE TABLE captures (
+        id INTEGER PRIMARY KEY,
+        capture_type TEXT NOT NULL,
+        captured_at TEXT NOT NULL,
+        plugin_version TEXT NOT NULL,
+        game_fingerprint TEXT,
+        raw_json TEXT NOT NULL
+    );
+    CREATE INDEX captures_type_time ON captures (capture_type, captured_at);
+
+    CREATE TABLE races (
+        capture_id INTEGER PRIMARY KEY REFERENCES captures (id) ON DELETE CASCADE,
+        content_hash TEXT,
+        race_type TEXT,
+        race_instance_id INTEGER,
+        track_id INTEGER,
+        course_id INTEGER,
+        distance INTEGER,
+        surface TEXT,
+        ground_condition TEXT,
+        weather TEXT,
+        season TEXT,
+        winner TEXT,
+        finish_time REAL
+    );
+    CREATE INDEX races_content_hash ON races (content_hash);
+
+    CREATE TABLE race_horses (
+        capture_id INTEGER NOT NULL REFERENCES races (capture_id) ON DELETE CASCADE,
+        horse_index INTEGER NOT NULL,
+        chara_id INTEGER,
+        chara_name TEXT,
+        finish_order INTEGER,
+        finish_time_raw REAL,
+        PRIMARY KEY (capture_id, horse_index)
+    );
+    CREATE INDEX race_horses_chara ON race_horses (chara_id);
+
+    CREATE TABLE team_trials (
+        capture_id INTEGER PRIMARY KEY REFERENCES captures (id) ON DELETE CASCADE
+    );
+
+    CREATE TABLE veteran_snapshots (
+        capture_id INTEGER PRIMARY KEY REFERENCES captures (id) ON DELETE CASCADE,
+        veteran_count INTEGER NOT NULL
+    );
+
+    CREATE TABLE veterans (
+        capture_id INTEGER NOT NULL REFERENCES veteran_snapshots (capture_id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        trained_chara_id INTEGER,
+        card_id INTEGER,
+        rank_score INTEGER,
+        PRIMARY KEY (capture_id, position)
+    );",
+    // 2: the races of each Team Trials round
+    "ALTER TABLE team_trials ADD COLUMN total_score INTEGER;
+
+    CREATE TABLE team_trial_races (
+        capture_id INTEGER NOT NULL REFERENCES team_trials (capture_id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        round INTEGER,
+        race_instance_id INTEGER,
+        win_type INTEGER,
+        PRIMARY KEY (capture_id, position)
+    );
+    CREATE INDEX team_trial_races_instance ON team_trial_races (race_instance_id);",
+];
+
+fn open(path: &Path) -> Result<Connection, String> {
+    let mut conn = Connection::open(path).map_err(|e| format!("open {}: {}", path.display(), e))?;
+    conn.pragma_update(None, "foreign_keys", true)
+        .map_err(|e| format!("enable foreign keys: {}", e))?;
+    conn.pragma_update(None, "journal_mode", "WAL")
+        .map_err(|e| format!("enable WAL: {}", e))?;
+    migrate(&mut conn)?;
+    Ok(conn)
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), String> {
+    let current: i64 = conn
+        .pragma_query_value(None, "user_version", |row| row.get(0))
+        .map_err(|e| format!("read schema version: {}", e))?;
+
+    if current as usize > MIGRATIONS.len() {
+        return Err(format!(
+            "database schema version {} is newer than this horseACT supports ({})",
+            current,
+            MIGRATIONS.len()
+        ));
+    }
+
+    for (i, sql) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        let version = i + 1;
+        let tx = conn
+            .transaction()
+            .map_err(|e| format!("begin migration {}: {}", version, e))?;
+        tx.execute_batch(sql)
+            .map_err(|e| format!("migration {}: {}", version, e))?;
+        tx.pragma_update(None, "user_version", version as i64)
+            .map_err(|e| format!("record migration {}: {}", version, e))?;
+        tx.commit()
+            .map_err(|e| format!("commit migration {}: {}", version, e))?;
+        log!("[SQLite] Migrated database to schema version {}", version);
+    }
+    Ok(())
+}
+
+/// Stores an enveloped capture, returning the id of its `captures` row.
+pub fn insert(path: &Path, capture_type: CaptureType, output: &Value) -> Result<i64, String> {
+    let mut guard = CONNECTIONS.lock().unwrap_or_else(|e| e.into_inner());
+    let connections = guard.get_or_insert_with(HashMap::new);
+    if !connections.contains_key(path) {
+        connections.insert(path.to_path_buf(), open(path)?);
+    }
+    let conn = connections
+        .get_mut(path)
+        .expect("connection was just opened");
+
+    let tx = conn
+        .transaction()
+        .map_err(|e| format!("begin transaction: {}", e))?;
+    let id = insert_capture(&tx, capture_type, output).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| format!("commit: {}", e))?;
+    Ok(id)
+}
+
+fn insert_capture(
+    tx: &Transaction,
+    capture_type: CaptureType,
+    output: &Value,
+) -> rusqlite::Result<i64> {
+    let str_field = |key: &str| output.get(key).and_then(|v| v.as_str());
+
+    tx.execute(
+        "INSERT INTO captures (capture_type, captured_at, plugin_version, game_fingerprint, raw_json)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            capture_type.as_str(),
+            output
+                .get("capturedAt")
+                .and_then(|v| v.get("utc"))
+                .and_then(|v| v.as_str())
+                .unwrap_or(""),
+            str_field("pluginVersion").unwrap_or(""),
+            str_field("gameAssemblyFingerprint"),
+            output.to_string(),
+        ],
+    )?;
+    let id = tx.last_insert_rowid();
+    let data = output.get("data").unwrap_or(&Value::Null);
+
+    match capture_type {
+        CaptureType::Race => insert_race(tx, id, output, data)?,
+        CaptureType::TeamTrials => insert_team_trials(tx, id, output, data)?,
+        CaptureType::Veterans => insert_veterans(tx, id, data)?,
+        // Kept in `captures` only; their layout depends on the endpoint.
+        CaptureType::ApiResponse => {}
+    }
+    Ok(id)
+}
+
+fn insert_race(tx: &Transaction, id: i64, output: &Value, data: &Value) -> rusqlite::Result<()> {
+    let course = output.get("horseACT_race").unwrap_or(&Value::Null);
+    let text = |v: Option<&Value>| v.map(value_to_plain_string);
+    let int = |v: Option<&Value>| v.and_then(|v| v.as_i64());
+
+    let (winner, finish_time) = crate::persistence::race_winner(data).unzip();
+
+    tx.execute(
+        "INSERT INTO races (capture_id, content_hash, race_type, race_instance_id, track_id,
+            course_id, distance, surface, ground_condition, weather, season, winner, finish_time)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        params![
+            id,
+            output.get("contentHash").and_then(|v| v.as_str()),
+            text(course.get("raceType")),
+            int(course.get("raceInstanceId")),
+            int(course.get("trackId")),
+            int(course.get("courseId")),
+            int(course.get("distance")),
+            text(course.get("surface")),
+            text(course.get("groundCondition")),
+            text(course.get("weather")),
+            text(course.get("season")),
+            winner,
+            finish_time,
+        ],
+    )?;
+
+    // Unreadable horses are left out; the rest keep their dump index.
+    let (race, _) = RaceInfo::from_value_lenient(data);
+    for horse in &race.horses {
+        tx.execute(
+            "INSERT INTO race_horses (capture_id, horse_index, chara_id, chara_name, finish_order, finish_time_raw)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                id,
+                horse.index as i64,
+                horse.chara_id,
+                horse.chara_name,
+                horse.finish_order,
+                horse.finish_time_raw,
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_team_trials(
+    tx: &Transaction,
+    id: i64,
+    output: &Value,
+    data: &Value,
+) -> rusqlite::Result<()> {
+    let summary = output.get("summary").unwrap_or(&Value::Null);
+    tx.execute(
+        "INSERT INTO team_trials (capture_id, total_score) VALUES (?1, ?2)",
+        params![id, summary.get("totalScore").and_then(|v| v.as_i64())],
+    )?;
+
+    // Split rounds may not keep the response; their summary lists the races.
+    let races: Vec<(Option<i64>, Option<i64>, Option<i64>)> =
+        match TeamTrialResult::from_value(data) {
+            Ok(result) => result
+                .races
+                .iter()
+                .map(|r| (r.round, r.race_instance_id, r.win_type))
+                .collect(),
+            Err(_) => summary
+                .get("races")
+                .and_then(|v| v.as_array())
+                .map(|races| {
+                    races
+                        .iter()
+                        .map(|r| {
+                            let int = |key: &str| r.get(key).and_then(|v| v.as_i64());
+                            (int("round"), int("raceInstanceId"), int("winType"))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        };
+    for (i, (round, race_instance_id, win_type)) in races.into_iter().enumerate() {
+        tx.execute(
+            "INSERT INTO team_trial_races (capture_id, position, round, race_instance_id, win_type)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![id, i as i64, round, race_instance_id, win_type],
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_veterans(tx: &Transaction, id: i64, data: &Value) -> rusqlite::Result<()> {
+    let veterans = data.as_array().map(|v| v.as_slice()).unwrap_or(&[]);
+    tx.execute(
+        "INSERT INTO veteran_snapshots (capture_id, veteran_count) VALUES (?1, ?2)",
+        params![id, veterans.len() as i64],
+    )?;
+
+    // Entries that cannot be read keep their position but get no row.
+    for (i, veteran) in veterans.iter().enumerate() {
+        let Ok(veteran) = TrainedChara::from_value(veteran) else {
+            continue;
+        };
+        tx.execute(
+            "INSERT INTO veterans (capture_id, position, trained_chara_id, card_id, rank_score)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                id,
+                i as i64,
+                veteran.trained_chara_id,
+                veteran.card_id,
+                veteran.rank_score,
+            ],
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use crate::models::{RaceHorse, RaceInfo, RunningStyle};
+use serde::Serialize;
+
+/// Readable result of a race, stored as `summary` in the envelope so the
+/// outcome is visible without reading `data`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RaceSummary {
+    pub horse_count: usize,
+    pub winner: Option<String>,
+    /// Winning time, e.g. `2:31.4`.
+    pub winning_time: Option<String>,
+    /// In finishing order.
+    pub placings: Vec<Placing>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Placing {
+    /// 1-based.
+    pub place: i64,
+    pub gate: Option<i64>,
+    pub name: Option<String>,
+    pub chara_id: Option<i64>,
+    /// Finish time as shown in game, e.g. `2:31.4`.
+    pub time: String,
+    /// Seconds behind the horse that finished just ahead, from the simulated
+    /// times. `None` for the winner.
+    pub margin: Option<f64>,
+    /// Seconds behind the winner.
+    pub behind_winner: f64,
+    pub strategy: Option<&'static str>,
+    pub popularity: Option<i64>,
+    /// Whether the horse belongs to the local player, when the capture
+    /// tells.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_mine: Option<bool>,
+    /// Horses sharing a slot have the same owner.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner_slot: Option<i64>,
+}
+
+impl RaceSummary {
+    pub fn from_race(race: &RaceInfo) -> Self {
+        let mut by_place: Vec<&RaceHorse> = race.horses.iter().collect();
+        by_place.sort_by_key(|h| h.finish_order);
+
+        let winner = by_place.first().copied();
+        let mut placings = Vec::with_capacity(by_place.len());
+        let mut previous: Option<&RaceHorse> = None;
+        for horse in by_place {
+            placings.push(Placing {
+                place: horse.finish_order + 1,
+                gate: horse.gate,
+                name: horse.chara_name.clone(),
+                chara_id: horse.chara_id,
+                time: format_race_time(displayed_time(horse)),
+                margin: previous
+                    .map(|p| round_hundredths(horse.finish_time_raw - p.finish_time_raw)),
+                behind_winner: winner
+                    .map(|w| round_hundredths(horse.finish_time_raw - w.finish_time_raw))
+                    .unwrap_or_default(),
+                strategy: horse.running_style.map(RunningStyle::name),
+                popularity: horse.popularity,
+                is_mine: horse.is_mine,
+                owner_slot: horse.owner_slot,
+            });
+            previous = Some(horse);
+        }
+
+        RaceSummary {
+            horse_count: race.horses.len(),
+            winner: winner.and_then(|w| w.chara_name.clone()),
+            winning_time: winner.map(|w| format_race_time(displayed_time(w))),
+            placings,
+        }
+    }
+}
+
+// The game shows `FinishTime`; dumps without it fall back to the simulated
+// time.
+fn displayed_time(horse: &RaceHorse) -> f64 {
+    horse.finish_time.unwrap_or(horse.finish_time_raw)
+}
+
+fn round_hundredths(seconds: f64) -> f64 {
+    (seconds * 100.0).round() / 100.0
+}
+
+/// Formats seconds as `m:ss.t`, e.g. `2:31.4`.
+pub fn format_race_time(seconds: f64) -> String {
+    let tenths = (seconds * 10.0).round() as i64;
+    format!("{}:{:02}.{}", tenths / 600, (tenths / 10) % 60, tenths % 10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn strategy_comes_from_running_style_name() {
+        let race_info = json!({
+            "<RaceHorse>k__BackingField": [
+                {
+                    "<FinishOrder>k__BackingField": 1,
+                    "<FinishTimeRaw>k__BackingField": 93.1,
+                    "<RunningStyle>k__BackingField": "Oikomi",
+                },
+                {
+                    "<FinishOrder>k__BackingField": 0,
+                    "<FinishTimeRaw>k__BackingField": 92.8,
+                    "<RunningStyle>k__BackingField": "Senko",
+                },
+            ],
+        });
+        let summary = RaceSummary::from_race(&RaceInfo::from_value(&race_info).unwrap());
+
+        let strategies: Vec<Option<&str>> = summary.placings.iter().map(|p| p.strategy).collect();
+        assert_eq!(strategies, [Some("Pace Chaser"), Some("End Closer")]);
+        assert_eq!(summary.placings[1].margin, Some(0.3));
+    }
+}
@@ -0,0 +1,167 @@
+use crate::log;
+use crate::lookup::{find_field, normalize_key};
+use crate::master::{self, NameKind};
+use crate::models::{RaceInfo, TeamTrialRace};
+use crate::simdata;
+use crate::summary::format_race_time;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// `<RaceType>` given to the races of a Team Trials result, as the game
+/// names Team Trials races.
+pub const RACE_TYPE: &str = "TeamStadium";
+
+// The result members sit on the response data, one or two levels down.
+const RESPONSE_SEARCH_DEPTH: usize = 3;
+
+/// Overview of one Team Trials round, stored as `summary` in the envelope.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoundSummary {
+    /// The opposing team as the response describes it.
+    pub opponent: Option<Value>,
+    /// In the order they were run.
+    pub races: Vec<RoundRace>,
+    /// Score entries of the response, bonuses included.
+    pub score_bonuses: Vec<Value>,
+    /// From the response, or the sum of the score entries.
+    pub total_score: Option<i64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoundRace {
+    pub round: Option<i64>,
+    pub race_instance_id: Option<i64>,
+    pub race_name: Option<String>,
+    /// Outcome of the race for the player's team, as the game encodes it.
+    pub win_type: Option<i64>,
+    pub winner: Option<String>,
+    pub winning_time: Option<String>,
+    /// Where the race was saved, relative to the save root.
+    pub file: Option<String>,
+}
+
+/// Rebuilds every race of a Team Trials result in the layout of a dumped
+/// `RaceInfo`, so it can be saved like any other race. The race's own
+/// members are kept; its scenario becomes `SimDataBase64` and its horses
+/// `RaceHorse`, each given its finish order and times from the simulation.
+pub fn split_races(response: &Value) -> Vec<Value> {
+    find_field(response, &["race_result_array"], RESPONSE_SEARCH_DEPTH)
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .map(race_info)
+        .collect()
+}
+
+fn take(map: &mut Map<String, Value>, name: &str) -> Option<Value> {
+    let wanted = normalize_key(name);
+    let key = map.keys().find(|k| normalize_key(k) == wanted)?.clone();
+    map.remove(&key)
+}
+
+fn race_info(race: &Value) -> Value {
+    let mut map = race.as_object().cloned().unwrap_or_default();
+    let scenario = take(&mut map, "race_scenario").unwrap_or(Value::Null);
+    let mut horses = take(&mut map, "race_horse_data_array")
+        .and_then(|v| match v {
+            Value::Array(arr) => Some(arr),
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    // Dumped races name their horses; the result only has chara ids.
+    for horse in horses.iter_mut() {
+        let name = find_field(horse, &["chara_id"], 1)
+            .and_then(|v| v.as_i64())
+            .and_then(|id| master::name(NameKind::Chara, id));
+        if let (Some(name), Value::Object(horse)) = (name, horse) {
+            horse
+                .entry("charaName".to_string())
+                .or_insert(Value::String(name));
+        }
+    }
+
+    match scenario.as_str().map(simdata::decode) {
+        Some(Ok(sim)) => {
+            for (i, horse) in horses.iter_mut().enumerate() {
+                // The simulation lists horses in gate order.
+                let gate = find_field(horse, &["frame_order"], 1)
+                    .and_then(|v| v.as_i64())
+                    .unwrap_or(i as i64 + 1);
+                let (Some(result), Value::Object(horse)) =
+                    (sim.horses.get((gate - 1) as usize), horse)
+                else {
+                    continue;
+                };
+                horse.insert("FinishOrder".to_string(), Value::from(result.finish_order));
+                horse.insert(
+                    "FinishTimeRaw".to_string(),
+                    Value::from(result.finish_time_raw),
+                );
+                horse.insert("FinishTime".to_string(), Value::from(result.finish_time));
+            }
+        }
+        Some(Err(e)) => {
+            log!("[TeamTrials] Could not decode a race scenario: {}", e);
+        }
+        None => {}
+    }
+
+    map.insert(
+        "<RaceType>k__BackingField".to_string(),
+        Value::from(RACE_TYPE),
+    );
+    map.insert("<SimDataBase64>k__BackingField".to_string(), scenario);
+    map.insert(
+        "<RaceHorse>k__BackingField".to_string(),
+        Value::Array(horses),
+    );
+    Value::Object(map)
+}
+
+impl RoundSummary {
+    /// `races` pairs each race of the result with its rebuilt `RaceInfo` and
+    /// where it was saved.
+    pub fn new(response: &Value, races: Vec<(TeamTrialRace, &Value, Option<String>)>) -> Self {
+        let field = |names: &[&str]| find_field(response, names, RESPONSE_SEARCH_DEPTH);
+
+        let score_bonuses: Vec<Value> = field(&["score_info_array", "bonus_score_info_array"])
+            .and_then(|v| v.as_array())
+            .cloned()
+            .unwrap_or_default();
+        let scores: Vec<i64> = score_bonuses
+            .iter()
+            .filter_map(|s| find_field(s, &["score"], 1).and_then(|v| v.as_i64()))
+            .collect();
+        let total_score = field(&["total_score"])
+            .and_then(|v| v.as_i64())
+            .or_else(|| (!scores.is_empty()).then(|| scores.iter().sum()));
+
+        RoundSummary {
+            opponent: field(&["opponent_info", "opponent_team", "opponent"]).cloned(),
+            races: races
+                .into_iter()
+                .map(|(race, race_info, file)| {
+                    let parsed = RaceInfo::from_value(race_info).ok();
+                    let winner = parsed.as_ref().and_then(|r| r.winner());
+                    RoundRace {
+                        round: race.round,
+                        race_instance_id: race.race_instance_id,
+                        race_name: race
+                            .race_instance_id
+                            .and_then(|id| master::name(NameKind::RaceInstance, id)),
+                        win_type: race.win_type,
+                        winner: winner.and_then(|w| w.chara_name.clone()),
+                        winning_time: winner
+                            .map(|w| format_race_time(w.finish_time.unwrap_or(w.finish_time_raw))),
+                        file,
+                    }
+                })
+                .collect(),
+            score_bonuses,
+            total_score,
+        }
+    }
+}
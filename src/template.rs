@@ -0,0 +1,172 @@
+use crate::log;
+use chrono::{DateTime, Local};
+use std::fmt::Write;
+use std::path::{Component, Path, PathBuf};
+
+pub struct TemplateContext {
+    captured_at: DateTime<Local>,
+    values: Vec<(&'static str, String)>,
+}
+
+impl TemplateContext {
+    pub fn new(captured_at: DateTime<Local>) -> Self {
+        TemplateContext {
+            captured_at,
+            values: Vec::new(),
+        }
+    }
+
+    pub fn set(&mut self, name: &'static str, value: impl Into<String>) {
+        let value = value.into();
+        match self.values.iter_mut().find(|(n, _)| *n == name) {
+            Some(entry) => entry.1 = value,
+            None => self.values.push((name, value)),
+        }
+    }
+
+    pub fn captured_at(&self) -> &DateTime<Local> {
+        &self.captured_at
+    }
+
+    fn lookup(&self, name: &str, format: Option<&str>) -> Option<String> {
+        match name {
+            "date" => Some(format_time(&self.captured_at, format.unwrap_or("%Y%m%d"))),
+            "timestamp" => Some(format_time(
+                &self.captured_at,
+                format.unwrap_or("%Y%m%d_%H%M%S_%3f"),
+            )),
+            _ => self
+                .values
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, v)| v.clone()),
+        }
+    }
+}
+
+fn format_time(time: &DateTime<Local>, format: &str) -> String {
+    // chrono panics on invalid specifiers when using to_string(), so go
+    // through write! which reports the error instead.
+    let mut out = String::new();
+    if write!(out, "{}", time.format(format)).is_err() {
+        log!("[Template] Invalid date format '{}', using %Y%m%d", format);
+        out = time.format("%Y%m%d").to_string();
+    }
+    out
+}
+
+pub fn sanitize_component(value: &str) -> String {
+    let safe: String = value
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == ' ' || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let safe = safe.trim().trim_matches('.').to_string();
+    if safe.is_empty() {
+        "_".to_string()
+    } else {
+        safe
+    }
+}
+
+/// Renders a template such as `{raceType}/{winner}_{date:%Y-%m}` into a path
+/// relative to the save root. Placeholder values are sanitized so they can
+/// never introduce extra directories; only literal `/` in the template does.
+pub fn render(template: &str, ctx: &TemplateContext) -> PathBuf {
+    let mut rendered = String::new();
+    let mut rest = template;
+
+    while let Some(open) = rest.find('{') {
+        rendered.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        let Some(close) = after.find('}') else {
+            rendered.push_str(&rest[open..]);
+            rest = "";
+            break;
+        };
+
+        let spec = &after[..close];
+        let (name, format) = match spec.split_once(':') {
+            Some((n, f)) => (n.trim(), Some(f)),
+            None => (spec.trim(), None),
+        };
+
+        let value = ctx.lookup(name, format).unwrap_or_else(|| {
+            log!("[Template] Unknown placeholder '{{{}}}'", name);
+            "Unknown".to_string()
+        });
+        rendered.push_str(&sanitize_component(&value));
+        rest = &after[close + 1..];
+    }
+    rendered.push_str(rest);
+
+    let mut path = PathBuf::new();
+    for part in rendered.split(['/', '\\']) {
+        let part = part.trim();
+        if part.is_empty() || part == "." || part == ".." {
+            continue;
+        }
+        path.push(part);
+    }
+
+    // Defensive: never let a rendered template escape the save root.
+    if path
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+        || path.as_os_str().is_empty()
+    {
+        return PathBuf::from("Unknown");
+    }
+    path
+}
+
+/// Drops `.extension` from the rendered path if the template spelled it out,
+/// since the extension is chosen by the writer.
+pub fn strip_extension(path: PathBuf, extension: &str) -> PathBuf {
+    let suffix = format!(".{}", extension);
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if name.len() > suffix.len() && name.to_ascii_lowercase().ends_with(&suffix) {
+        let trimmed = name[..name.len() - suffix.len()].to_string();
+        path.with_file_name(trimmed)
+    } else {
+        path
+    }
+}
+
+pub fn with_extension(base: &Path, extension: &str) -> PathBuf {
+    let name = base
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    base.with_file_name(format!("{}.{}", name, extension))
+}
+
+/// Appends `.extension` to `base`, adding `-1`, `-2`, ... to the file name
+/// until the path is unused.
+pub fn unique_path(base: &Path, extension: &str) -> PathBuf {
+    let candidate = with_extension(base, extension);
+    if !candidate.exists() {
+        return candidate;
+    }
+
+    let name = base
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut n = 1;
+    loop {
+        let candidate = base.with_file_name(format!("{}-{}.{}", name, n, extension));
+        if !candidate.exists() {
+            return candidate;
+        }
+        n += 1;
+    }
+}
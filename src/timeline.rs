@@ -0,0 +1,257 @@
+use crate::master::{self, NameKind};
+use crate::models::RaceInfo;
+use crate::simdata::{Frame, RaceSimulation};
+use serde::Serialize;
+use serde_json::Value;
+
+// Event type the simulation uses for skill activations; params are the
+// horse index and the skill id.
+const SKILL_EVENT: i8 = 3;
+
+// A new position is only reported once it has been held this long, so
+// horses running side by side do not flood the timeline.
+const POSITION_HOLD_SECS: f32 = 1.0;
+
+/// One readable moment of a race. Horses are identified by their index in
+/// the simulation, i.e. gate order.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimelineEvent {
+    pub time: f32,
+    pub horse: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub horse_name: Option<String>,
+    #[serde(flatten)]
+    pub kind: TimelineKind,
+    /// 1-based running position at `time`.
+    pub position: Option<usize>,
+    /// Metres from the start at `time`.
+    pub distance: Option<f32>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum TimelineKind {
+    #[serde(rename_all = "camelCase")]
+    Skill {
+        skill_id: i32,
+        /// Resolved from the master database, when it is available.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        skill_name: Option<String>,
+    },
+    PositionChange {
+        from: usize,
+    },
+    LastSpurt,
+    StaminaExhausted,
+    /// `place` is 1-based.
+    Finish {
+        place: i32,
+    },
+}
+
+impl TimelineKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TimelineKind::Skill { .. } => "skill",
+            TimelineKind::PositionChange { .. } => "positionChange",
+            TimelineKind::LastSpurt => "lastSpurt",
+            TimelineKind::StaminaExhausted => "staminaExhausted",
+            TimelineKind::Finish { .. } => "finish",
+        }
+    }
+}
+
+/// Chara names of the horses in simulation order. Horses that cannot be
+/// read have no name.
+pub fn horse_names(race_info: &Value) -> Vec<Option<String>> {
+    let (race, _) = RaceInfo::from_value_lenient(race_info);
+    race.horses_by_slot()
+        .into_iter()
+        .map(|h| h.and_then(|h| h.chara_name.clone()))
+        .collect()
+}
+
+/// Derives skill activations, position changes, last spurt starts, stamina
+/// exhaustion and finish crossings from a decoded simulation, sorted by time.
+pub fn build(sim: &RaceSimulation, names: &[Option<String>]) -> Vec<TimelineEvent> {
+    let mut events = Vec::new();
+    let mut push = |time: f32, horse: usize, kind: TimelineKind| {
+        events.push(TimelineEvent {
+            time,
+            horse,
+            horse_name: names.get(horse).cloned().flatten(),
+            kind,
+            position: frame_at(sim, time).and_then(|f| position(f, horse)),
+            distance: distance_at(sim, horse, time),
+        });
+    };
+
+    for event in sim.events.iter().filter(|e| e.kind == SKILL_EVENT) {
+        if let (Some(&horse), Some(&skill_id)) = (event.params.first(), event.params.get(1)) {
+            if let Ok(horse) = usize::try_from(horse) {
+                let skill_name = master::name(NameKind::Skill, i64::from(skill_id));
+                push(
+                    event.frame_time,
+                    horse,
+                    TimelineKind::Skill {
+                        skill_id,
+                        skill_name,
+                    },
+                );
+            }
+        }
+    }
+
+    for (horse, result) in sim.horses.iter().enumerate() {
+        if result.last_spurt_start_distance > 0.0 {
+            if let Some(time) = time_at_distance(sim, horse, result.last_spurt_start_distance) {
+                push(time, horse, TimelineKind::LastSpurt);
+            }
+        }
+
+        let exhausted = sim
+            .frames
+            .iter()
+            .skip(1)
+            .find(|f| f.horses.get(horse).is_some_and(|h| h.hp == 0));
+        if let Some(frame) = exhausted {
+            push(frame.time, horse, TimelineKind::StaminaExhausted);
+        }
+
+        push(
+            result.finish_time_raw,
+            horse,
+            TimelineKind::Finish {
+                place: result.finish_order + 1,
+            },
+        );
+    }
+
+    for (time, horse, from) in position_changes(sim) {
+        push(time, horse, TimelineKind::PositionChange { from });
+    }
+
+    events.sort_by(|a, b| a.time.total_cmp(&b.time).then(a.horse.cmp(&b.horse)));
+    events
+}
+
+// (time, horse, previous position) for every new position held at least
+// POSITION_HOLD_SECS, timed from when it was taken.
+fn position_changes(sim: &RaceSimulation) -> Vec<(f32, usize, usize)> {
+    let mut changes = Vec::new();
+    let Some(first) = sim.frames.first() else {
+        return changes;
+    };
+
+    // Per horse: reported position, and the position it is currently
+    // holding with the time it first took it.
+    let mut reported: Vec<Option<usize>> =
+        (0..sim.horse_count).map(|h| position(first, h)).collect();
+    let mut holding: Vec<Option<(usize, f32)>> = vec![None; sim.horse_count];
+
+    for frame in &sim.frames[1..] {
+        for horse in 0..sim.horse_count {
+            let Some(current) = position(frame, horse) else {
+                continue;
+            };
+            if Some(current) == reported[horse] {
+                holding[horse] = None;
+                continue;
+            }
+            let since = match holding[horse] {
+                Some((held, since)) if held == current => since,
+                _ => {
+                    holding[horse] = Some((current, frame.time));
+                    frame.time
+                }
+            };
+            if frame.time - since >= POSITION_HOLD_SECS {
+                if let Some(from) = reported[horse] {
+                    changes.push((since, horse, from));
+                }
+                reported[horse] = Some(current);
+                holding[horse] = None;
+            }
+        }
+    }
+    changes
+}
+
+fn position(frame: &Frame, horse: usize) -> Option<usize> {
+    let distance = frame.horses.get(horse)?.distance;
+    Some(
+        1 + frame
+            .horses
+            .iter()
+            .filter(|h| h.distance > distance)
+            .count(),
+    )
+}
+
+// Last frame at or before `time`.
+fn frame_at(sim: &RaceSimulation, time: f32) -> Option<&Frame> {
+    let i = sim.frames.partition_point(|f| f.time <= time);
+    sim.frames.get(i.checked_sub(1)?)
+}
+
+fn distance_at(sim: &RaceSimulation, horse: usize, time: f32) -> Option<f32> {
+    let i = sim.frames.partition_point(|f| f.time <= time);
+    let before = sim.frames.get(i.checked_sub(1)?)?;
+    let d0 = before.horses.get(horse)?.distance;
+    match sim
+        .frames
+        .get(i)
+        .and_then(|f| Some((f.time, f.horses.get(horse)?.distance)))
+    {
+        Some((t1, d1)) if t1 > before.time => {
+            Some(d0 + (d1 - d0) * (time - before.time) / (t1 - before.time))
+        }
+        _ => Some(d0),
+    }
+}
+
+fn time_at_distance(sim: &RaceSimulation, horse: usize, distance: f32) -> Option<f32> {
+    let mut previous: Option<(f32, f32)> = None;
+    for frame in &sim.frames {
+        let d = frame.horses.get(horse)?.distance;
+        if d >= distance {
+            return Some(match previous {
+                Some((t0, d0)) if d > d0 => t0 + (frame.time - t0) * (distance - d0) / (d - d0),
+                _ => frame.time,
+            });
+        }
+        previous = Some((frame.time, d));
+    }
+    None
+}
+
+/// The timeline as CSV, one event per row.
+pub fn to_csv(events: &[TimelineEvent]) -> String {
+    let mut out = String::from("time,horse,horseName,type,position,distance,detail\r\n");
+    for event in events {
+        let detail = match &event.kind {
+            TimelineKind::Skill { skill_id, .. } => skill_id.to_string(),
+            TimelineKind::PositionChange { from } => from.to_string(),
+            TimelineKind::Finish { place } => place.to_string(),
+            TimelineKind::LastSpurt | TimelineKind::StaminaExhausted => String::new(),
+        };
+        let name = event.horse_name.as_deref().unwrap_or("");
+        let name = if name.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", name.replace('"', "\"\""))
+        } else {
+            name.to_string()
+        };
+        out.push_str(&format!(
+            "{},{},{},{},{},{},{}\r\n",
+            event.time,
+            event.horse,
+            name,
+            event.kind.name(),
+            event.position.map(|p| p.to_string()).unwrap_or_default(),
+            event.distance.map(|d| d.to_string()).unwrap_or_default(),
+            detail
+        ));
+    }
+    out
+}
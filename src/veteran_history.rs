@@ -0,0 +1,206 @@
+use crate::config::save_root;
+use crate::dedup::content_hash;
+use crate::log;
+use crate::models::TrainedChara;
+use crate::persistence::write_atomic;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Folder of the save root holding the veteran history. It is never scanned
+/// for captures.
+pub const VETERAN_HISTORY_DIR: &str = "Veteran history";
+
+const SNAPSHOT_DIR: &str = "snapshots";
+const BY_ID_DIR: &str = "by-id";
+const CHANGE_LOG_FILE: &str = "changes.jsonl";
+const STATE_FILE: &str = ".state.json";
+
+/// Hash of every member of every veteran of the last stored snapshot, keyed
+/// by trained chara id, then by member name.
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq)]
+struct State {
+    veterans: BTreeMap<i64, BTreeMap<String, String>>,
+}
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+/// Differences between a veteran list and the last stored snapshot.
+pub struct Changes {
+    state: State,
+    pub added: Vec<i64>,
+    pub removed: Vec<i64>,
+    /// Trained chara ids with the names of their changed members.
+    pub modified: Vec<(i64, Vec<String>)>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+fn history_dir() -> PathBuf {
+    save_root().join(VETERAN_HISTORY_DIR)
+}
+
+fn load_state() -> State {
+    fs::read(history_dir().join(STATE_FILE))
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+fn trained_chara_id(chara: &Value) -> Option<i64> {
+    TrainedChara::from_value(chara)
+        .ok()
+        .map(|c| c.trained_chara_id)
+}
+
+// Member hashes are shortened; they only need to tell versions of the same
+// member apart.
+fn member_hashes(chara: &Value) -> BTreeMap<String, String> {
+    match chara {
+        Value::Object(map) => map
+            .iter()
+            .filter(|(k, _)| !k.starts_with("horseACT_"))
+            .map(|(k, v)| (k.clone(), content_hash(v)[..16].to_string()))
+            .collect(),
+        other => BTreeMap::from([(String::new(), content_hash(other)[..16].to_string())]),
+    }
+}
+
+/// Compares a veteran list with the last stored snapshot. Veterans without a
+/// trained chara id cannot be followed and are left out.
+pub fn diff(list: &Value) -> Changes {
+    let mut state = State::default();
+    for chara in list.as_array().into_iter().flatten() {
+        if let Some(id) = trained_chara_id(chara) {
+            state.veterans.insert(id, member_hashes(chara));
+        }
+    }
+
+    let mut guard = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let previous = guard.get_or_insert_with(load_state);
+
+    let added = state
+        .veterans
+        .keys()
+        .filter(|id| !previous.veterans.contains_key(id))
+        .copied()
+        .collect();
+    let removed = previous
+        .veterans
+        .keys()
+        .filter(|id| !state.veterans.contains_key(id))
+        .copied()
+        .collect();
+    let modified = state
+        .veterans
+        .iter()
+        .filter_map(|(id, members)| {
+            let old = previous.veterans.get(id)?;
+            let mut changed: Vec<String> = members
+                .iter()
+                .filter(|(k, hash)| old.get(*k) != Some(*hash))
+                .map(|(k, _)| k.clone())
+                .collect();
+            changed.extend(old.keys().filter(|k| !members.contains_key(*k)).cloned());
+            (!changed.is_empty()).then_some((*id, changed))
+        })
+        .collect();
+
+    Changes {
+        state,
+        added,
+        removed,
+        modified,
+    }
+}
+
+/// Stores a changed veteran list: the whole capture as a new snapshot, every
+/// added or modified veteran under `by-id/<trainedCharaId>.json` and a line
+/// in the change log. The list becomes the base of the next [`diff`] once
+/// everything is written.
+pub fn record(changes: Changes, output: &Value, captured_at: chrono::DateTime<chrono::Local>) {
+    match write(&changes, output, captured_at) {
+        Ok(snapshot) => {
+            log!(
+                "[Veteran] Stored snapshot {}: {} added, {} removed, {} modified",
+                snapshot,
+                changes.added.len(),
+                changes.removed.len(),
+                changes.modified.len()
+            );
+            *STATE.lock().unwrap_or_else(|e| e.into_inner()) = Some(changes.state);
+        }
+        Err(e) => {
+            log!("[Veteran] Failed to store the veteran history: {}", e);
+        }
+    }
+}
+
+fn write(
+    changes: &Changes,
+    output: &Value,
+    captured_at: chrono::DateTime<chrono::Local>,
+) -> Result<String, String> {
+    let dir = history_dir();
+    fs::create_dir_all(dir.join(SNAPSHOT_DIR)).map_err(|e| e.to_string())?;
+    fs::create_dir_all(dir.join(BY_ID_DIR)).map_err(|e| e.to_string())?;
+
+    let snapshot = format!(
+        "{}/{}.json",
+        SNAPSHOT_DIR,
+        captured_at.format("%Y%m%d_%H%M%S_%3f")
+    );
+    let bytes = serde_json::to_vec_pretty(output).map_err(|e| e.to_string())?;
+    write_atomic(&dir.join(&snapshot), &bytes).map_err(|e| e.to_string())?;
+
+    let updated: Vec<i64> = changes
+        .added
+        .iter()
+        .copied()
+        .chain(changes.modified.iter().map(|(id, _)| *id))
+        .collect();
+    for chara in output["data"].as_array().into_iter().flatten() {
+        let Some(id) = trained_chara_id(chara).filter(|id| updated.contains(id)) else {
+            continue;
+        };
+        let entry = json!({
+            "capturedAt": output["capturedAt"],
+            "snapshot": snapshot,
+            "data": chara,
+        });
+        let bytes = serde_json::to_vec_pretty(&entry).map_err(|e| e.to_string())?;
+        write_atomic(&dir.join(BY_ID_DIR).join(format!("{}.json", id)), &bytes)
+            .map_err(|e| e.to_string())?;
+    }
+
+    let line = json!({
+        "capturedAt": output["capturedAt"]["utc"],
+        "snapshot": snapshot,
+        "count": changes.state.veterans.len(),
+        "added": changes.added,
+        "removed": changes.removed,
+        "modified": changes
+            .modified
+            .iter()
+            .map(|(id, members)| json!({ "trainedCharaId": id, "members": members }))
+            .collect::<Vec<_>>(),
+    });
+    let mut log_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(CHANGE_LOG_FILE))
+        .map_err(|e| e.to_string())?;
+    writeln!(log_file, "{}", line).map_err(|e| e.to_string())?;
+
+    let state = serde_json::to_vec(&changes.state).map_err(|e| e.to_string())?;
+    write_atomic(&dir.join(STATE_FILE), &state).map_err(|e| e.to_string())?;
+    Ok(snapshot)
+}